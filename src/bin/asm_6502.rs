@@ -1,24 +1,36 @@
 #[cfg(feature = "cli")]
 use argparse::{ArgumentParser, Collect, Print, Store, StoreFalse, StoreOption, StoreTrue};
 
-use asm_6502::{Asm6502, Result};
+use std::io::IsTerminal;
+use std::path::PathBuf;
 
-fn assemble(input: String, offset: u16) -> Result<Vec<u8>> {
-    let mut result = vec![];
-    let mut asm = Asm6502::new(input, offset);
+use asm_6502::diagnostic::Renderer;
+use asm_6502::error::CompileError;
+use asm_6502::lint::{Level, LintLevels};
+use asm_6502::ops::{CpuModel, CpuOptions};
+use asm_6502::{Asm6502, DecompileOptions};
+
+fn assemble(asm: &mut Asm6502, pad: u8) -> Result<Vec<u8>, Vec<CompileError>> {
     asm.compile()?;
-    for instruction in asm.instructions {
-        let (_, mut bytes) = instruction.clone().into();
-        result.append(&mut bytes);
-    }
-    Ok(result)
+    Ok(asm.to_binary(pad))
 }
 
+// The argument bindings are only written to when the argument parser is compiled in.
+#[cfg_attr(not(feature = "cli"), allow(unused_mut))]
 fn main() {
     let mut filepath: Option<String> = None;
     let mut output_filepath: Option<String> = None;
     let mut decompile = false;
     let mut memory_offset: String = "8000".to_string();
+    let mut pad: String = "00".to_string();
+    let mut decompile_options = DecompileOptions::default();
+    let mut cpu = CpuOptions::default();
+    let mut model: String = "6502".to_string();
+    let mut nes_symbols = false;
+    let mut allow: Vec<String> = vec![];
+    let mut warn: Vec<String> = vec![];
+    let mut deny: Vec<String> = vec![];
+    let mut include_paths: Vec<String> = vec![];
     let mut input: Vec<String> = vec![];
     #[cfg(feature = "cli")]
     {
@@ -26,10 +38,7 @@ fn main() {
         ap.set_description("A rusty 6502 assembler/disassembler.");
         ap.add_option(
             &["-v", "--version"],
-            Print(format!(
-                "ASM 6502\n{}",
-                env!("CARGO_PKG_VERSION").to_string()
-            )),
+            Print(format!("ASM 6502\n{}", env!("CARGO_PKG_VERSION"))),
             "Display the version.",
         );
         ap.refer(&mut output_filepath).add_option(
@@ -47,6 +56,11 @@ fn main() {
             Store,
             "The memory offset to start the program at.",
         );
+        ap.refer(&mut pad).add_option(
+            &["-p", "--pad"],
+            Store,
+            "The byte, in hex, filling the gaps between origins. (Default 00)",
+        );
         ap.refer(&mut decompile)
             .add_option(
                 &["-d", "--disassemble"],
@@ -58,25 +72,121 @@ fn main() {
                 StoreFalse,
                 "Assemble the input or file. (Default)",
             );
+        ap.refer(&mut decompile_options.accumulator_operand)
+            .add_option(
+                &["--accumulator-operand"],
+                StoreTrue,
+                "Disassemble accumulator shifts and rotates as `ASL A` rather than `ASL`.",
+            );
+        ap.refer(&mut model).add_option(
+            &["-c", "--cpu"],
+            Store,
+            "The processor to assemble for: 6502, 65c02, r65c02 (Rockwell), w65c02 (WDC), 65816 or 2a03 (NES). (Default 6502)",
+        );
+        ap.refer(&mut cpu.allow_illegal).add_option(
+            &["--illegal"],
+            StoreTrue,
+            "Allow the undocumented NMOS opcodes, written without a `*` prefix.",
+        );
+        ap.refer(&mut nes_symbols).add_option(
+            &["--nes-symbols"],
+            StoreTrue,
+            "Predefine the NES registers, such as PPUCTRL and OAMDMA.",
+        );
+        ap.refer(&mut include_paths).add_option(
+            &["-I", "--include"],
+            Collect,
+            "A directory searched by .include and .incbin, after the directory of the including file.",
+        );
+        ap.refer(&mut allow).add_option(
+            &["-A", "--allow"],
+            Collect,
+            "Silence a lint, such as unused-label.",
+        );
+        ap.refer(&mut warn)
+            .add_option(&["-W", "--warn"], Collect, "Report a lint as a warning.");
+        ap.refer(&mut deny).add_option(
+            &["-D", "--deny"],
+            Collect,
+            "Report a lint as an error, `-D warnings` denies every warning.",
+        );
         ap.refer(&mut input)
             .add_argument("input", Collect, "Direct source input.");
         ap.parse_args_or_exit();
     }
-    let offset = u16::from_str_radix(&memory_offset, 16).unwrap();
-    if filepath.is_some() {
+    let offset = match u32::from_str_radix(&memory_offset, 16) {
+        Ok(offset) => offset,
+        Err(_) => {
+            eprintln!("invalid offset `{}`, expected a hex address", memory_offset);
+            std::process::exit(2);
+        }
+    };
+    let pad = match u8::from_str_radix(&pad, 16) {
+        Ok(pad) => pad,
+        Err(_) => {
+            eprintln!("invalid pad `{}`, expected a hex byte", pad);
+            std::process::exit(2);
+        }
+    };
+    cpu.model = match CpuModel::from_name(&model) {
+        Some(model) => model,
+        None => {
+            eprintln!("unknown cpu `{}`", model);
+            std::process::exit(2);
+        }
+    };
+    let mut lints = LintLevels::default();
+    for (names, level) in [
+        (allow, Level::Allow),
+        (warn, Level::Warn),
+        (deny, Level::Deny),
+    ] {
+        for name in names {
+            if let Err(err) = lints.set_by_name(&name, level) {
+                eprintln!("{}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+    let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let renderer = Renderer::new(color);
+    if let Some(filepath) = filepath {
         // Ignore input and load file.
         input.clear();
         if decompile {
-            let filedata = std::fs::read(filepath.unwrap()).unwrap();
-            let result = Asm6502::decompile(filedata, offset).join("\n");
+            let filedata = match std::fs::read(&filepath) {
+                Ok(filedata) => filedata,
+                Err(err) => {
+                    eprintln!("cannot read `{}`: {}", filepath, err);
+                    std::process::exit(2);
+                }
+            };
+            decompile_options.cpu = cpu;
+            let result = Asm6502::decompile_with(filedata, offset, &decompile_options).join("\n");
             if let Some(out_file) = output_filepath {
                 std::fs::write(out_file, result).unwrap();
             } else {
                 println!("{}", result)
             }
         } else {
-            let filedata = std::fs::read_to_string(filepath.unwrap()).unwrap();
-            match assemble(filedata.clone(), offset) {
+            let filedata = match std::fs::read_to_string(&filepath) {
+                Ok(filedata) => filedata,
+                Err(err) => {
+                    eprintln!("cannot read `{}`: {}", filepath, err);
+                    std::process::exit(2);
+                }
+            };
+            let mut asm = Asm6502::new(filedata, offset);
+            asm.file = Some(filepath);
+            asm.cpu = cpu;
+            asm.lints = lints;
+            asm.nes_symbols = nes_symbols;
+            asm.include_paths = include_paths.iter().map(PathBuf::from).collect();
+            let result = assemble(&mut asm, pad);
+            for warning in &asm.warnings {
+                eprintln!("{}", renderer.render_warning(warning));
+            }
+            match result {
                 Ok(output) => {
                     if let Some(out_file) = output_filepath {
                         std::fs::write(out_file, output).unwrap();
@@ -91,17 +201,18 @@ fn main() {
                         );
                     }
                 }
-                Err(err) => {
-                    if cfg!(debug_assertions) {
-                        println!("FILEDATA:\n{}", filedata);
+                Err(errors) => {
+                    for err in &errors {
+                        eprintln!("{}", renderer.render(err));
                     }
-                    panic!("{}", err);
+                    eprintln!("aborting due to {} error(s)", errors.len());
+                    std::process::exit(1);
                 }
             }
         }
-    } else if input.len() > 0 {
-        // Input is available to compile.
-        todo!("Handle raw input here");
+    } else if !input.is_empty() {
+        eprintln!("direct source input is not supported, pass a file with `-f`");
+        std::process::exit(2);
     } else {
         std::process::exit(0);
     }
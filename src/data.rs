@@ -0,0 +1,233 @@
+use std::fmt::Display;
+
+use crate::encoding::{Charset, Encoding};
+use crate::error::{CompileError, ErrorKind};
+use crate::expr::{EvalError, Expr};
+use crate::lexer::{Token, TokenKind};
+use crate::parser::Statement;
+use crate::symbols::SymbolTable;
+use crate::{Fixup, FixupKind, InstructionAddress, Result};
+
+/// Bytes emitted by a data directive such as `.byte` or `.word`.
+#[derive(Clone, Debug)]
+pub struct Data {
+    pub directive: String,
+    pub bytes: Vec<u8>,
+    pub address: InstructionAddress,
+}
+
+/// Evaluate a value that decides where the following code goes, such as an origin or a
+/// count, and so cannot refer to a label defined further down.
+pub(crate) fn known_value(
+    symbols: &SymbolTable,
+    tokens: &[Token],
+    statement: &Statement,
+    pc: u32,
+) -> Result<i64> {
+    let column = match tokens.first() {
+        Some(token) => token.column,
+        None => {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                statement.line,
+                "missing value",
+            ))
+        }
+    };
+    let expr = Expr::parse(tokens, statement.line)?;
+    expr.eval(&|name| symbols.value(name), pc)
+        .map_err(|err| match err {
+            EvalError::Undefined(name) => CompileError::at(
+                ErrorKind::ForwardReference(name.clone()),
+                statement.line,
+                column,
+                format!("value of `{}` must be known at this point", name),
+            ),
+            err => err.into_error(statement.line, column),
+        })
+}
+
+/// Split the operand of a directive on commas, except those inside parentheses or brackets
+/// such as the one of a macro argument `($10,X)`.
+pub(crate) fn split_arguments(operand: &[Token]) -> Vec<&[Token]> {
+    if operand.is_empty() {
+        return vec![];
+    }
+    let mut depth = 0;
+    operand
+        .split(|token| {
+            if token.is_symbol("(") || token.is_symbol("[") {
+                depth += 1;
+            } else if token.is_symbol(")") || token.is_symbol("]") {
+                depth -= 1;
+            }
+            depth == 0 && token.is_symbol(",")
+        })
+        .collect()
+}
+
+impl Data {
+    /// Encode a data directive.
+    ///
+    /// Symbols that are not defined yet are returned as fixups with an index of 0, to be set
+    /// by the caller once the data has its place in the output. Strings are encoded with
+    /// `charset`.
+    pub(crate) fn from_statement(
+        symbols: &SymbolTable,
+        statement: &Statement,
+        pc: u32,
+        charset: &Charset,
+    ) -> Result<(Self, Vec<Fixup>)> {
+        let directive = statement
+            .mnemonic
+            .as_ref()
+            .expect("directive statements have a mnemonic");
+        let line = statement.line;
+        let arguments = split_arguments(&statement.operand);
+        let mut bytes: Vec<u8> = vec![];
+        let mut fixups = vec![];
+
+        // Evaluate one argument, leaving forward references to be patched at `offset`.
+        let mut value = |argument: &[Token], kind: FixupKind, offset: usize| -> Result<i64> {
+            let column = match argument.first() {
+                Some(token) => token.column,
+                None => {
+                    return Err(CompileError::at(
+                        ErrorKind::SyntaxError,
+                        line,
+                        directive.column,
+                        "missing value",
+                    ))
+                }
+            };
+            let expr = Expr::parse(argument, line)?;
+            match expr.eval(&|name| symbols.value(name), pc) {
+                Ok(value) => Ok(value),
+                Err(EvalError::Undefined(_)) => {
+                    fixups.push(Fixup {
+                        index: 0,
+                        offset,
+                        kind,
+                        expr,
+                        line,
+                        column,
+                        notes: vec![],
+                        scope: symbols.scope.clone(),
+                    });
+                    Ok(0)
+                }
+                Err(err) => Err(err.into_error(line, column)),
+            }
+        };
+
+        match directive.text.as_str() {
+            name if matches!(name, ".byte" | ".db" | ".text" | ".asciiz")
+                || Encoding::from_directive(name).is_some() =>
+            {
+                let encoding = charset.encoding_of(statement);
+                if arguments.is_empty() {
+                    return Err(CompileError::at(
+                        ErrorKind::SyntaxError,
+                        line,
+                        directive.column,
+                        "missing value",
+                    ));
+                }
+                for argument in &arguments {
+                    if let [token] = argument {
+                        if token.kind == TokenKind::String {
+                            bytes.extend(charset.encode_text(
+                                encoding,
+                                &token.text,
+                                line,
+                                token.column,
+                            )?);
+                            continue;
+                        }
+                    }
+                    let offset = bytes.len();
+                    let v = value(argument, FixupKind::Byte, offset)?;
+                    bytes.push(FixupKind::Byte.encode(v, argument[0].column, line)?[0]);
+                }
+                if directive.text == ".asciiz" {
+                    bytes.push(0);
+                }
+            }
+            ".word" | ".dw" | ".dword" => {
+                let kind = if directive.text == ".dword" {
+                    FixupKind::Dword
+                } else {
+                    FixupKind::Word
+                };
+                if arguments.is_empty() {
+                    return Err(CompileError::at(
+                        ErrorKind::SyntaxError,
+                        line,
+                        directive.column,
+                        "missing value",
+                    ));
+                }
+                for argument in &arguments {
+                    let offset = bytes.len();
+                    let v = value(argument, kind.clone(), offset)?;
+                    bytes.append(&mut kind.encode(v, argument[0].column, line)?);
+                }
+            }
+            ".fill" | ".res" => {
+                let (count, fill) = match &arguments[..] {
+                    [count] => (count, None),
+                    [count, fill] => (count, Some(fill)),
+                    _ => {
+                        return Err(CompileError::at(
+                            ErrorKind::SyntaxError,
+                            line,
+                            directive.column,
+                            "expected a count and an optional fill value",
+                        ))
+                    }
+                };
+                // The count decides where the following code goes, so it must be known now.
+                let count = known_value(symbols, count, statement, pc)?;
+                if !(0..=0xffff).contains(&count) {
+                    return Err(CompileError::at(
+                        ErrorKind::ValueOutOfRange,
+                        line,
+                        directive.column,
+                        "value out of range",
+                    ));
+                }
+                let fill = match fill {
+                    Some(fill) => {
+                        let v = known_value(symbols, fill, statement, pc)?;
+                        FixupKind::Byte.encode(v, fill[0].column, line)?[0]
+                    }
+                    None => 0,
+                };
+                bytes = vec![fill; count as usize];
+            }
+            _ => {
+                return Err(CompileError::at(
+                    ErrorKind::UnknownDirective,
+                    line,
+                    directive.column,
+                    "unknown directive",
+                ))
+            }
+        }
+        Ok((
+            Data {
+                directive: directive.text.clone(),
+                bytes,
+                address: statement.address.clone(),
+            },
+            fixups,
+        ))
+    }
+}
+
+impl Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("${:02X}", b)).collect();
+        write!(f, "{}\t.byte {}", self.address, bytes.join(", "))
+    }
+}
@@ -0,0 +1,176 @@
+use crate::error::{CompileError, ErrorKind};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const GREEN: &str = "\x1b[1;32m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders errors the way rustc does, with the offending source line and a caret under the
+/// offending token.
+#[derive(Clone, Debug, Default)]
+pub struct Renderer {
+    /// Highlight the output with ANSI escape codes.
+    pub color: bool,
+}
+
+/// Number of characters of the token starting at `column` of `source`, for underlining it.
+fn token_width(source: &str, column: usize) -> usize {
+    let chars: Vec<char> = source.chars().skip(column - 1).collect();
+    match chars.first() {
+        None => 1,
+        Some('#') if chars.len() > 1 => 1 + token_width(source, column + 1),
+        Some(quote @ ('\'' | '"')) => chars[1..]
+            .iter()
+            .position(|c| c == quote)
+            .map_or(chars.len(), |end| end + 2),
+        Some(c) if c.is_ascii_alphanumeric() || "$%_.@*".contains(*c) => {
+            1 + chars[1..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                .count()
+        }
+        Some(_) => 1,
+    }
+}
+
+impl Renderer {
+    pub fn new(color: bool) -> Self {
+        Renderer { color }
+    }
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+    /// Write the `file:line:col` location and the source excerpt it points at.
+    fn excerpt(
+        &self,
+        out: &mut String,
+        file: Option<&str>,
+        line: usize,
+        column: Option<usize>,
+        source: Option<&str>,
+        caret_style: &str,
+    ) {
+        let number = line.to_string();
+        let gutter = " ".repeat(number.len());
+        let mut location = format!("{}:{}", file.unwrap_or("<input>"), line);
+        if let Some(column) = column {
+            location.push_str(&format!(":{}", column));
+        }
+        out.push_str(&format!(
+            "{}{} {}\n",
+            gutter,
+            self.paint(BLUE, "-->"),
+            location
+        ));
+        let source = match source {
+            Some(source) => source,
+            None => return,
+        };
+        let bar = self.paint(BLUE, "|");
+        out.push_str(&format!("{} {}\n", gutter, bar));
+        out.push_str(&format!(
+            "{} {} {}\n",
+            self.paint(BLUE, &number),
+            bar,
+            source
+        ));
+        if let Some(column) = column {
+            // Keep tabs so the caret lines up with the source above.
+            let indent: String = source
+                .chars()
+                .take(column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let carets = "^".repeat(token_width(source, column));
+            out.push_str(&format!(
+                "{} {} {}{}\n",
+                gutter,
+                bar,
+                indent,
+                self.paint(caret_style, &carets)
+            ));
+        }
+    }
+    /// Render an error and its notes, ending with a newline.
+    pub fn render(&self, err: &CompileError) -> String {
+        self.render_as("error", RED, err)
+    }
+    /// Render a warning reported by a lint, ending with a newline.
+    pub fn render_warning(&self, warning: &CompileError) -> String {
+        self.render_as("warning", YELLOW, warning)
+    }
+    fn render_as(&self, level: &str, style: &str, err: &CompileError) -> String {
+        // Lints are named after the level, so that they can be looked up to allow them.
+        let title = match &err.kind {
+            ErrorKind::Lint(lint) => format!("{}[{}]", level, lint.name()),
+            _ => level.to_string(),
+        };
+        let mut out = format!(
+            "{}{}\n",
+            self.paint(style, &title),
+            self.paint(BOLD, &format!(": {}", err.message))
+        );
+        self.excerpt(
+            &mut out,
+            err.file.as_deref(),
+            err.line,
+            err.column,
+            err.source.as_deref(),
+            style,
+        );
+        for note in &err.notes {
+            out.push_str(&format!(
+                "{}{}\n",
+                self.paint(GREEN, "note"),
+                self.paint(BOLD, &format!(": {}", note.message))
+            ));
+            self.excerpt(
+                &mut out,
+                note.file.as_deref(),
+                note.line,
+                note.column,
+                note.source.as_deref(),
+                GREEN,
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Asm6502;
+
+    #[test]
+    fn source_excerpts() {
+        let mut asm = Asm6502::new("start: NOP\n\tLDA #$100\nstart: RTS".to_string(), 0x8000);
+        asm.file = Some("game.s".to_string());
+        let errors = asm.compile().unwrap_err();
+        let renderer = Renderer::new(false);
+        assert_eq!(
+            renderer.render(&errors[0]),
+            "error[truncated-immediate]: immediate value does not fit in a byte\n --> game.s:2:6\n  |\n2 | \tLDA #$100\n  | \t    ^^^^^\n"
+        );
+        assert_eq!(
+            renderer.render(&errors[1]),
+            "error: duplicate definition of `start`\n --> game.s:3\n  |\n3 | start: RTS\nnote: first defined here\n --> game.s:1\n  |\n1 | start: NOP\n"
+        );
+        assert!(Renderer::new(true)
+            .render(&errors[0])
+            .starts_with("\x1b[1;31merror[truncated-immediate]\x1b[0m"));
+        assert_eq!(token_width("  LDA 'A'+1", 7), 3);
+        let mut asm = Asm6502::new("  *LAX $10".to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(
+            renderer.render_warning(&asm.warnings[0]),
+            "warning[undocumented-opcode]: undocumented opcode `*LAX`\n --> <input>:1:3\n  |\n1 |   *LAX $10\n  |   ^^^^\n"
+        );
+    }
+}
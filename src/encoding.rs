@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use crate::error::{CompileError, ErrorKind};
+use crate::lexer::TokenKind;
+use crate::parser::Statement;
+use crate::Result;
+
+/// Character set that strings and character literals are written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// 7-bit ASCII, the default.
+    #[default]
+    Ascii,
+    /// Commodore PETSCII, in the mixed case character set: `a` is $41 and `A` is $C1.
+    Petscii,
+    /// Commodore 64 screen codes, as written to screen memory.
+    Screen,
+    /// Atari ATASCII.
+    Atascii,
+    /// Atari internal codes, as written to screen memory for ANTIC.
+    Antic,
+}
+
+fn ascii(c: char) -> Option<u8> {
+    Some(c as u8).filter(|_| c.is_ascii())
+}
+
+fn petscii(c: char) -> Option<u8> {
+    match c {
+        'a'..='z' => Some(c as u8 - b'a' + 0x41),
+        'A'..='Z' => Some(c as u8 - b'A' + 0xc1),
+        ' '..='@' | '[' | ']' => Some(c as u8),
+        '£' => Some(0x5c),
+        '↑' => Some(0x5e),
+        '←' => Some(0x5f),
+        'π' => Some(0xff),
+        '\n' | '\r' => Some(0x0d),
+        _ => None,
+    }
+}
+
+fn screen(c: char) -> Option<u8> {
+    // Screen codes follow PETSCII by blocks of 32 characters.
+    match petscii(c)? {
+        code @ 0x20..=0x3f => Some(code),
+        code @ 0x40..=0x5f => Some(code - 0x40),
+        code @ 0xc0..=0xdf => Some(code - 0x80),
+        0xff => Some(0x5e),
+        _ => None,
+    }
+}
+
+fn atascii(c: char) -> Option<u8> {
+    match c {
+        ' '..='_' | 'a'..='z' | '|' => Some(c as u8),
+        '\n' => Some(0x9b),
+        _ => None,
+    }
+}
+
+fn antic(c: char) -> Option<u8> {
+    match atascii(c)? {
+        code @ 0x20..=0x5f => Some(code - 0x20),
+        code @ 0x60..=0x7f => Some(code),
+        _ => None,
+    }
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 5] = [
+        Encoding::Ascii,
+        Encoding::Petscii,
+        Encoding::Screen,
+        Encoding::Atascii,
+        Encoding::Antic,
+    ];
+    /// Name given to `.encoding`, which is also the string directive using the encoding
+    /// without the `.`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Ascii => "ascii",
+            Encoding::Petscii => "petscii",
+            Encoding::Screen => "screen",
+            Encoding::Atascii => "atascii",
+            Encoding::Antic => "antic",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Encoding::ALL
+            .iter()
+            .find(|encoding| encoding.name().eq_ignore_ascii_case(name))
+            .cloned()
+    }
+    /// Encoding of a string directive such as `.petscii`.
+    pub fn from_directive(directive: &str) -> Option<Self> {
+        Encoding::ALL
+            .iter()
+            .find(|encoding| directive.strip_prefix('.') == Some(encoding.name()))
+            .cloned()
+    }
+    /// Code of `c`, if the character set has it.
+    pub fn encode(self, c: char) -> Option<u8> {
+        match self {
+            Encoding::Ascii => ascii(c),
+            Encoding::Petscii => petscii(c),
+            Encoding::Screen => screen(c),
+            Encoding::Atascii => atascii(c),
+            Encoding::Antic => antic(c),
+        }
+    }
+}
+
+/// Encoding selected with `.encoding`, and the codes changed with `.charmap` in each encoding.
+#[derive(Clone, Debug, Default)]
+pub struct Charset {
+    pub encoding: Encoding,
+    charmaps: HashMap<Encoding, HashMap<char, u8>>,
+}
+
+impl Charset {
+    pub fn new() -> Self {
+        Charset::default()
+    }
+    /// Give `c` the code `code` in the current encoding.
+    pub fn map(&mut self, c: char, code: u8) {
+        self.charmaps
+            .entry(self.encoding)
+            .or_default()
+            .insert(c, code);
+    }
+    /// Code of `c` in `encoding`, following the changes made with `.charmap`.
+    pub fn encode(&self, encoding: Encoding, c: char) -> Option<u8> {
+        self.charmaps
+            .get(&encoding)
+            .and_then(|charmap| charmap.get(&c))
+            .copied()
+            .or_else(|| encoding.encode(c))
+    }
+    /// Encode the text of a string or character literal starting at `column`.
+    pub(crate) fn encode_text(
+        &self,
+        encoding: Encoding,
+        text: &str,
+        line: usize,
+        column: usize,
+    ) -> Result<Vec<u8>> {
+        text.chars()
+            .map(|c| {
+                self.encode(encoding, c).ok_or_else(|| {
+                    CompileError::at(
+                        ErrorKind::ValueOutOfRange,
+                        line,
+                        column,
+                        format!(
+                            "`{}` has no code in {}",
+                            c.escape_default(),
+                            encoding.name()
+                        ),
+                    )
+                })
+            })
+            .collect()
+    }
+    /// Encoding used by `statement`: the encoding of its directive for a string directive such
+    /// as `.petscii`, and the current one otherwise.
+    pub(crate) fn encoding_of(&self, statement: &Statement) -> Encoding {
+        statement
+            .mnemonic
+            .as_ref()
+            .and_then(|mnemonic| Encoding::from_directive(&mnemonic.text))
+            .unwrap_or(self.encoding)
+    }
+    /// Replace the character literals of the operand of `statement` by their codes.
+    pub(crate) fn encode_characters(&self, statement: &mut Statement) -> Result<()> {
+        let encoding = self.encoding_of(statement);
+        for token in statement.operand.iter_mut() {
+            if token.kind == TokenKind::Character {
+                let code = self.encode_text(encoding, &token.text, statement.line, token.column)?;
+                token.kind = TokenKind::Number;
+                token.text = code[0].to_string();
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,19 +1,109 @@
 use std::fmt::Display;
 
-#[derive(Debug)]
+use crate::lint::Lint;
+
+/// What went wrong, independently of the wording of the message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// Malformed source, such as a stray character or a missing operand.
+    SyntaxError,
+    UnknownMnemonic,
+    UnknownDirective,
+    InvalidAddressingMode,
+    UndefinedSymbol(String),
+    /// A value that decides the layout of the output refers to a symbol defined further down.
+    ForwardReference(String),
+    DuplicateLabel(String),
+    ValueOutOfRange,
+    BranchOutOfRange,
+    /// Division by zero or overflow while evaluating an expression.
+    Arithmetic,
+    /// Output running past $FFFF.
+    AddressOutOfRange,
+    OverlappingRegions,
+    /// A file named by `.include` or `.incbin` that can't be found or read.
+    Io,
+    /// A file including itself, directly or through the files it includes.
+    IncludeCycle,
+    /// A lint that is denied.
+    Lint(Lint),
+}
+
+/// Secondary information pointing at another place in the source.
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: Option<usize>,
+    pub message: String,
+    /// Text of the source line pointed at.
+    pub source: Option<String>,
+}
+
+impl Note {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        Note {
+            file: None,
+            line,
+            column: None,
+            message: message.into(),
+            source: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct CompileError {
+    pub kind: ErrorKind,
+    /// Source file, when the source did not come from memory.
+    pub file: Option<String>,
     pub line: usize,
-    pub message: &'static str,
+    /// Column of the offending token, starting at 1, when it is known.
+    pub column: Option<usize>,
+    pub message: String,
+    /// Text of the offending source line.
+    pub source: Option<String>,
+    pub notes: Vec<Note>,
 }
 
 impl CompileError {
-    pub fn new(line: usize, message: &'static str) -> Self {
-        CompileError { line, message }
+    pub fn new(kind: ErrorKind, line: usize, message: impl Into<String>) -> Self {
+        CompileError {
+            kind,
+            file: None,
+            line,
+            column: None,
+            message: message.into(),
+            source: None,
+            notes: vec![],
+        }
+    }
+    pub fn at(kind: ErrorKind, line: usize, column: usize, message: impl Into<String>) -> Self {
+        CompileError {
+            column: Some(column),
+            ..CompileError::new(kind, line, message)
+        }
+    }
+    pub fn with_note(mut self, note: Note) -> Self {
+        self.notes.push(note);
+        self
     }
 }
 
 impl Display for CompileError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "compile error on line {}: {}", self.line, self.message)
+        write!(f, "compile error ")?;
+        if let Some(file) = &self.file {
+            write!(f, "in {} ", file)?;
+        }
+        if let Some(column) = self.column {
+            write!(
+                f,
+                "on line {}, column {}: {}",
+                self.line, column, self.message
+            )
+        } else {
+            write!(f, "on line {}: {}", self.line, self.message)
+        }
     }
 }
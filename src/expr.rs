@@ -0,0 +1,343 @@
+use crate::error::{CompileError, ErrorKind};
+use crate::lexer::{Token, TokenKind};
+use crate::Result;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    /// Bitwise complement, `~`.
+    Complement,
+    /// Logical not, `!`.
+    Not,
+    /// Low byte, `<`.
+    Low,
+    /// High byte, `>`.
+    High,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BinaryOp {
+    Multiply,
+    Divide,
+    Modulo,
+    Add,
+    Subtract,
+    ShiftLeft,
+    ShiftRight,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// Look up a binary operator and its precedence, higher binding tighter.
+    fn from_token(token: &Token) -> Option<(Self, u8)> {
+        if token.kind != TokenKind::Symbol {
+            return None;
+        }
+        Some(match token.text.as_str() {
+            "*" => (BinaryOp::Multiply, 10),
+            "/" => (BinaryOp::Divide, 10),
+            "%" => (BinaryOp::Modulo, 10),
+            "+" => (BinaryOp::Add, 9),
+            "-" => (BinaryOp::Subtract, 9),
+            "<<" => (BinaryOp::ShiftLeft, 8),
+            ">>" => (BinaryOp::ShiftRight, 8),
+            "<" => (BinaryOp::Less, 7),
+            "<=" => (BinaryOp::LessEqual, 7),
+            ">" => (BinaryOp::Greater, 7),
+            ">=" => (BinaryOp::GreaterEqual, 7),
+            "==" => (BinaryOp::Equal, 6),
+            "!=" => (BinaryOp::NotEqual, 6),
+            "&" => (BinaryOp::BitAnd, 5),
+            "^" => (BinaryOp::BitXor, 4),
+            "|" => (BinaryOp::BitOr, 3),
+            "&&" => (BinaryOp::And, 2),
+            "||" => (BinaryOp::Or, 1),
+            _ => return None,
+        })
+    }
+}
+
+/// A constant expression used as an operand or directive argument.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    /// The address of the current instruction or directive, `*`.
+    ProgramCounter,
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+/// Why an expression could not be evaluated.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalError {
+    /// The symbol is not defined, at least not yet.
+    Undefined(String),
+    DivisionByZero,
+    Overflow,
+}
+
+impl EvalError {
+    /// Report the failure for the expression at `column` of `line`.
+    pub fn into_error(self, line: usize, column: usize) -> CompileError {
+        let (kind, message) = match self {
+            EvalError::Undefined(name) => (
+                ErrorKind::UndefinedSymbol(name.clone()),
+                format!("undefined symbol `{}`", name),
+            ),
+            EvalError::DivisionByZero => (ErrorKind::Arithmetic, "division by zero".to_string()),
+            EvalError::Overflow => (ErrorKind::Arithmetic, "arithmetic overflow".to_string()),
+        };
+        CompileError::at(kind, line, column, message)
+    }
+}
+
+/// Parse a numeric or character literal.
+pub fn literal_value(token: &Token) -> Option<i64> {
+    match token.kind {
+        TokenKind::Number => {
+            let text = &token.text;
+            if let Some(hex) = text.strip_prefix('$') {
+                i64::from_str_radix(hex, 16).ok()
+            } else if let Some(bin) = text.strip_prefix('%') {
+                i64::from_str_radix(bin, 2).ok()
+            } else {
+                text.parse().ok()
+            }
+        }
+        TokenKind::Character => token.text.chars().next().map(|c| c as i64),
+        _ => None,
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &'static str) -> CompileError {
+        match self
+            .tokens
+            .get(self.position)
+            .or_else(|| self.tokens.last())
+        {
+            Some(token) => {
+                CompileError::at(ErrorKind::SyntaxError, self.line, token.column, message)
+            }
+            None => CompileError::new(ErrorKind::SyntaxError, self.line, message),
+        }
+    }
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr> {
+        let mut left = self.unary()?;
+        while let Some((op, precedence)) = self
+            .tokens
+            .get(self.position)
+            .and_then(BinaryOp::from_token)
+        {
+            if precedence < min_precedence {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+    fn unary(&mut self) -> Result<Expr> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => {
+                self.position -= 1;
+                return Err(self.error("expected a value"));
+            }
+        };
+        let op = match token.text.as_str() {
+            _ if token.kind != TokenKind::Symbol => None,
+            "-" => Some(UnaryOp::Negate),
+            "~" => Some(UnaryOp::Complement),
+            "!" => Some(UnaryOp::Not),
+            "<" => Some(UnaryOp::Low),
+            ">" => Some(UnaryOp::High),
+            _ => None,
+        };
+        if let Some(op) = op {
+            return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+        }
+        match token.kind {
+            TokenKind::Number | TokenKind::Character => match literal_value(token) {
+                Some(value) => Ok(Expr::Number(value)),
+                None => {
+                    self.position -= 1;
+                    Err(self.error("invalid number"))
+                }
+            },
+            TokenKind::Identifier => Ok(Expr::Symbol(token.text.clone())),
+            TokenKind::Symbol if token.text == "*" => Ok(Expr::ProgramCounter),
+            TokenKind::Symbol if token.text == "(" => {
+                let inner = self.binary(0)?;
+                match self.next() {
+                    Some(close) if close.is_symbol(")") => Ok(inner),
+                    _ => {
+                        self.position -= 1;
+                        Err(self.error("expected `)`"))
+                    }
+                }
+            }
+            _ => {
+                self.position -= 1;
+                Err(self.error("expected a value"))
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// Parse tokens making up a whole expression.
+    pub fn parse(tokens: &[Token], line: usize) -> Result<Self> {
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            line,
+        };
+        let expr = parser.binary(0)?;
+        if parser.position < tokens.len() {
+            return Err(parser.error("unexpected token"));
+        }
+        Ok(expr)
+    }
+    /// Evaluate the expression, looking symbols up with `resolve`.
+    ///
+    /// `pc` is the value of `*`.
+    pub fn eval(
+        &self,
+        resolve: &dyn Fn(&str) -> Option<i64>,
+        pc: u32,
+    ) -> std::result::Result<i64, EvalError> {
+        Ok(match self {
+            Expr::Number(value) => *value,
+            Expr::Symbol(name) => {
+                resolve(name).ok_or_else(|| EvalError::Undefined(name.clone()))?
+            }
+            Expr::ProgramCounter => pc as i64,
+            Expr::Unary(op, operand) => {
+                let value = operand.eval(resolve, pc)?;
+                match op {
+                    UnaryOp::Negate => value.checked_neg().ok_or(EvalError::Overflow)?,
+                    UnaryOp::Complement => !value,
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Low => value & 0xff,
+                    UnaryOp::High => (value >> 8) & 0xff,
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(resolve, pc)?;
+                let right = right.eval(resolve, pc)?;
+                match op {
+                    BinaryOp::Multiply => left.checked_mul(right).ok_or(EvalError::Overflow)?,
+                    BinaryOp::Divide | BinaryOp::Modulo if right == 0 => {
+                        return Err(EvalError::DivisionByZero)
+                    }
+                    BinaryOp::Divide => left.checked_div(right).ok_or(EvalError::Overflow)?,
+                    BinaryOp::Modulo => left.checked_rem(right).ok_or(EvalError::Overflow)?,
+                    BinaryOp::Add => left.checked_add(right).ok_or(EvalError::Overflow)?,
+                    BinaryOp::Subtract => left.checked_sub(right).ok_or(EvalError::Overflow)?,
+                    BinaryOp::ShiftLeft | BinaryOp::ShiftRight if !(0..64).contains(&right) => {
+                        return Err(EvalError::Overflow)
+                    }
+                    BinaryOp::ShiftLeft => {
+                        left.checked_mul(1 << right).ok_or(EvalError::Overflow)?
+                    }
+                    BinaryOp::ShiftRight => left >> right,
+                    BinaryOp::Less => (left < right) as i64,
+                    BinaryOp::LessEqual => (left <= right) as i64,
+                    BinaryOp::Greater => (left > right) as i64,
+                    BinaryOp::GreaterEqual => (left >= right) as i64,
+                    BinaryOp::Equal => (left == right) as i64,
+                    BinaryOp::NotEqual => (left != right) as i64,
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::BitXor => left ^ right,
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::And => (left != 0 && right != 0) as i64,
+                    BinaryOp::Or => (left != 0 || right != 0) as i64,
+                }
+            }
+        })
+    }
+    /// Check whether the value is an address, because it depends on the program counter or a
+    /// symbol for which `is_address_symbol` holds, other than through its low or high byte.
+    pub fn is_address(&self, is_address_symbol: &dyn Fn(&str) -> bool) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Symbol(name) => is_address_symbol(name),
+            Expr::ProgramCounter => true,
+            Expr::Unary(UnaryOp::Low, _) | Expr::Unary(UnaryOp::High, _) => false,
+            Expr::Unary(_, operand) => operand.is_address(is_address_symbol),
+            Expr::Binary(_, left, right) => {
+                left.is_address(is_address_symbol) || right.is_address(is_address_symbol)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    fn eval(source: &str) -> std::result::Result<i64, EvalError> {
+        let line = &tokenize(source).0[0];
+        let expr = Expr::parse(&line.tokens, line.number).unwrap();
+        expr.eval(
+            &|name| match name {
+                "vector" => Some(0x1234),
+                "row" => Some(3),
+                _ => None,
+            },
+            0x8000,
+        )
+    }
+
+    #[test]
+    fn precedence_and_operators() {
+        assert_eq!(eval("$0400+40*row"), Ok(0x0400 + 120));
+        assert_eq!(eval("(1+2)*3"), Ok(9));
+        assert_eq!(eval("<vector"), Ok(0x34));
+        assert_eq!(eval(">vector"), Ok(0x12));
+        assert_eq!(eval("vector+1"), Ok(0x1235));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("$F0 | %1010 & 3 ^ 1"), Ok(0xf3));
+        assert_eq!(eval("'A' + 1"), Ok(66));
+        assert_eq!(eval("*-2"), Ok(0x7ffe));
+        assert_eq!(eval("7%4"), Ok(3));
+        assert_eq!(eval("row%10"), Ok(3));
+        assert_eq!(eval("-1 < 0 && 2 == 2"), Ok(1));
+        assert_eq!(eval("1/0"), Err(EvalError::DivisionByZero));
+        assert_eq!(eval("1 << 70"), Err(EvalError::Overflow));
+        assert_eq!(
+            eval("missing+1"),
+            Err(EvalError::Undefined("missing".to_string()))
+        );
+        let line = &tokenize("(1+2").0[0];
+        assert_eq!(
+            Expr::parse(&line.tokens, 1).unwrap_err().message,
+            "expected `)`"
+        );
+    }
+}
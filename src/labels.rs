@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use crate::lexer::{Token, TokenKind};
+use crate::parser::Statement;
+use crate::symbols::qualify;
+use crate::InstructionAddress;
+
+/// Renames local, numbered and anonymous labels to unique names, line by line.
+///
+/// Local labels, written `@loop`, `.loop` or `_loop`, belong to the last global label and are
+/// renamed `global::@loop`. Numbered labels, `1:`, are referenced with `1b` for the last one
+/// defined and `1f` for the next one. Anonymous labels are runs of `-` or `+` at the start of
+/// a line, and an operand made of the same run refers to the last `-` or the next `+`.
+#[derive(Clone, Debug, Default)]
+pub struct LocalLabels {
+    /// Last global label, which the local labels belong to.
+    pub(crate) scope: String,
+    /// Number of definitions so far of each numbered or anonymous label.
+    counts: HashMap<String, usize>,
+}
+
+/// Check whether `name` is written as a local label.
+pub fn is_local(name: &str) -> bool {
+    name.len() > 1 && name.starts_with(['@', '.', '_'])
+}
+
+/// Check whether `name` was given to a numbered or anonymous label.
+pub fn is_numbered(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+')
+}
+
+/// Split a run of `-` or `+` symbols written next to each other from the start of `tokens`.
+fn anonymous_run(tokens: &[Token]) -> Option<(String, usize)> {
+    let first = tokens.first()?;
+    if !(first.is_symbol("-") || first.is_symbol("+")) {
+        return None;
+    }
+    let length = tokens
+        .iter()
+        .enumerate()
+        .take_while(|(i, token)| token.text == first.text && token.column == first.column + i)
+        .count();
+    Some((first.text.repeat(length), length))
+}
+
+impl LocalLabels {
+    pub fn new() -> Self {
+        LocalLabels::default()
+    }
+    /// Name of the `count`th definition of a numbered or anonymous label.
+    fn numbered(name: &str, count: usize) -> String {
+        format!("{}@{}", name, count)
+    }
+    /// Rename a numbered or anonymous label defined at the start of `tokens`, which can't be
+    /// parsed otherwise, into a label followed by `:`.
+    pub fn define(&mut self, tokens: &[Token]) -> Vec<Token> {
+        let (name, length) = match tokens {
+            [number, colon, ..]
+                if number.kind == TokenKind::Number
+                    && number.text.chars().all(|c| c.is_ascii_digit())
+                    && colon.is_symbol(":") =>
+            {
+                (number.text.clone(), 2)
+            }
+            _ => match anonymous_run(tokens) {
+                Some(run) => run,
+                None => return tokens.to_vec(),
+            },
+        };
+        let count = self.counts.entry(name.clone()).or_insert(0);
+        *count += 1;
+        let column = tokens[0].column;
+        let mut renamed = vec![
+            Token::new(
+                TokenKind::Identifier,
+                LocalLabels::numbered(&name, *count),
+                column,
+            ),
+            Token::new(TokenKind::Symbol, ":".to_string(), column),
+        ];
+        renamed.extend_from_slice(&tokens[length..]);
+        renamed
+    }
+    /// Rename the local labels of `statement` and its references to numbered and anonymous
+    /// labels, after starting a new scope if it defines a global label.
+    pub fn localize(&mut self, statement: &mut Statement) {
+        let constant = statement
+            .mnemonic
+            .as_ref()
+            .is_some_and(|mnemonic| mnemonic.text == ".equ" || mnemonic.text == ".set");
+        if let InstructionAddress::Label(name) = &mut statement.address {
+            if is_local(name) {
+                *name = qualify(&self.scope, name);
+            } else if !constant && !is_numbered(name) && !name.contains('#') {
+                // Labels a macro renames for each expansion stay inside the caller's scope.
+                self.scope = name.clone();
+            }
+        }
+        if let Some((name, length)) = anonymous_run(&statement.operand) {
+            if length == statement.operand.len() {
+                let count = self.counts.get(&name).copied().unwrap_or(0);
+                let count = if name.starts_with('-') {
+                    count
+                } else {
+                    count + 1
+                };
+                let column = statement.operand[0].column;
+                statement.operand = vec![Token::new(
+                    TokenKind::Identifier,
+                    LocalLabels::numbered(&name, count),
+                    column,
+                )];
+                return;
+            }
+        }
+        for token in statement.operand.iter_mut() {
+            match token.kind {
+                TokenKind::Identifier if is_local(&token.text) => {
+                    token.text = qualify(&self.scope, &token.text);
+                }
+                TokenKind::Number
+                    if token.text.len() > 1
+                        && token.text.ends_with(['b', 'f'])
+                        && token.text[..token.text.len() - 1]
+                            .chars()
+                            .all(|c| c.is_ascii_digit()) =>
+                {
+                    let (name, direction) = token.text.split_at(token.text.len() - 1);
+                    let count = self.counts.get(name).copied().unwrap_or(0);
+                    let count = if direction == "b" { count } else { count + 1 };
+                    token.kind = TokenKind::Identifier;
+                    token.text = LocalLabels::numbered(name, count);
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::error::{CompileError, ErrorKind};
+use crate::Result;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    /// Mnemonics, labels, directives and register names.
+    Identifier,
+    /// Numeric literal, keeping its prefix (`$FF`, `%0101`, `255`).
+    Number,
+    /// Character literal, the text is the character itself.
+    Character,
+    /// String literal, the text is the unescaped contents.
+    String,
+    /// Punctuation and operators.
+    Symbol,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    /// Column of the first character of the token, starting at 1.
+    pub column: usize,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, text: String, column: usize) -> Self {
+        Token { kind, text, column }
+    }
+    /// Check whether the token is the given punctuation or operator.
+    pub fn is_symbol(&self, symbol: &str) -> bool {
+        self.kind == TokenKind::Symbol && self.text == symbol
+    }
+}
+
+/// The tokens of a single source line.
+#[derive(Clone, Debug)]
+pub struct Line {
+    /// Line number in the source, starting at 1.
+    pub number: usize,
+    pub tokens: Vec<Token>,
+    /// Whether the first token is followed by a tab, as the address of each line in the
+    /// listings the disassembler writes.
+    pub tabbed: bool,
+}
+
+/// Operators made of two characters, matched before single character symbols.
+const DOUBLE_SYMBOLS: [&str; 9] = ["<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "::"];
+
+const SINGLE_SYMBOLS: &str = "#,():=+-*/%&|^~!<>[]";
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '@'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Check whether `chars` start with `::` followed by a name.
+fn starts_qualified(chars: &[char]) -> bool {
+    matches!(chars, [':', ':', c, ..] if is_identifier_start(*c))
+}
+
+/// Check whether a token can end a value, making a following `%` the modulo operator
+/// rather than the start of a binary literal.
+fn ends_value(token: &Token) -> bool {
+    match token.kind {
+        TokenKind::Symbol => token.text == ")",
+        _ => true,
+    }
+}
+
+fn unescape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        '0' => Some('\0'),
+        '\\' | '\'' | '"' => Some(c),
+        _ => None,
+    }
+}
+
+/// Split source code into lines of tokens.
+///
+/// `;` starts a comment running to the end of the line and `/* */` comments may span
+/// several lines. Lines left without any token are omitted, as are lines with errors, which
+/// are returned separately so that the rest of the source can still be checked.
+pub fn tokenize(source: &str) -> (Vec<Line>, Vec<CompileError>) {
+    let mut lines = vec![];
+    let mut errors = vec![];
+    let mut in_comment: Option<(usize, usize)> = None;
+    for (index, text) in source.lines().enumerate() {
+        let number = index + 1;
+        match tokenize_line(number, text, &mut in_comment) {
+            Ok(tokens) if tokens.is_empty() => {}
+            Ok(tokens) => {
+                let first = &tokens[0];
+                let tabbed = text
+                    .chars()
+                    .nth(first.column - 1 + first.text.chars().count())
+                    == Some('\t');
+                lines.push(Line {
+                    number,
+                    tokens,
+                    tabbed,
+                })
+            }
+            Err(err) => errors.push(err),
+        }
+    }
+    if let Some((line, column)) = in_comment {
+        errors.push(CompileError::at(
+            ErrorKind::SyntaxError,
+            line,
+            column,
+            "unterminated block comment",
+        ));
+    }
+    (lines, errors)
+}
+
+/// Split a single line into tokens, tracking where a block comment left open started.
+fn tokenize_line(
+    number: usize,
+    text: &str,
+    in_comment: &mut Option<(usize, usize)>,
+) -> Result<Vec<Token>> {
+    let error = |column, message| CompileError::at(ErrorKind::SyntaxError, number, column, message);
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if in_comment.is_some() {
+            if c == '*' && chars.get(i + 1) == Some(&'/') {
+                *in_comment = None;
+                i += 2;
+            } else {
+                i += 1;
+            }
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == ';' {
+            break;
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            *in_comment = Some((number, column));
+            i += 2;
+        } else if is_identifier_start(c) || starts_qualified(&chars[i..]) {
+            let start = i;
+            if c == ':' {
+                // `::name` is looked up at the top level.
+                i += 2;
+            }
+            i += 1;
+            loop {
+                while i < chars.len() && is_identifier_char(chars[i]) {
+                    i += 1;
+                }
+                // A name qualified by its scopes, as `draw::loop`, is a single identifier.
+                if !starts_qualified(&chars[i..]) {
+                    break;
+                }
+                i += 3;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(Token::new(TokenKind::Identifier, word, column));
+        } else if c.is_ascii_digit()
+            || (c == '$' && chars.get(i + 1).is_some_and(|c| c.is_ascii_hexdigit()))
+            || (c == '%'
+                && chars.get(i + 1).is_some_and(|c| *c == '0' || *c == '1')
+                && !tokens.last().is_some_and(ends_value))
+        {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            tokens.push(Token::new(TokenKind::Number, literal, column));
+        } else if c == '\'' || c == '"' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(error(column, "unterminated literal")),
+                    Some(&end) if end == c => break,
+                    Some('\\') => {
+                        match chars.get(i + 1).and_then(|c| unescape(*c)) {
+                            Some(escaped) => value.push(escaped),
+                            None => return Err(error(i + 1, "invalid escape sequence")),
+                        }
+                        i += 2;
+                    }
+                    Some(other) => {
+                        value.push(*other);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            if c == '\'' {
+                if value.chars().count() != 1 {
+                    return Err(error(
+                        column,
+                        "character literal must contain exactly one character",
+                    ));
+                }
+                tokens.push(Token::new(TokenKind::Character, value, column));
+            } else {
+                tokens.push(Token::new(TokenKind::String, value, column));
+            }
+        } else {
+            let pair: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            if DOUBLE_SYMBOLS.contains(&pair.as_str()) {
+                tokens.push(Token::new(TokenKind::Symbol, pair, column));
+                i += 2;
+            } else if SINGLE_SYMBOLS.contains(c) || c == '$' {
+                tokens.push(Token::new(TokenKind::Symbol, c.to_string(), column));
+                i += 1;
+            } else {
+                return Err(error(column, "unexpected character"));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn comments_and_whitespace() {
+        let (lines, errors) = tokenize(
+            "; header\n\n  loop:   LDA ( $15 , X ) ; load\n/* block\n comment */ TAX /* inline */\n\t\n",
+        );
+        assert!(errors.is_empty());
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].number, 3);
+        let texts: Vec<&str> = lines[0].tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["loop", ":", "LDA", "(", "$15", ",", "X", ")"]);
+        assert_eq!(lines[0].tokens[2].column, 11);
+        assert_eq!(lines[1].number, 5);
+        assert_eq!(lines[1].tokens[0].text, "TAX");
+        assert_eq!(lines[1].tokens[0].column, 13);
+        let (lines, errors) = tokenize("NOP\n  LDA #'AB'\n  LDX ?\n/* open");
+        assert_eq!(lines.len(), 1);
+        let positions: Vec<(usize, Option<usize>)> =
+            errors.iter().map(|err| (err.line, err.column)).collect();
+        assert_eq!(positions, vec![(2, Some(8)), (3, Some(7)), (4, Some(1))]);
+    }
+}
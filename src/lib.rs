@@ -1,20 +1,49 @@
+// Errors carry their location and source text, they are only built once something failed.
+#![allow(clippy::result_large_err)]
+
 #[macro_use]
 extern crate lazy_static;
 
+pub mod data;
+pub mod diagnostic;
+pub mod encoding;
 pub mod error;
+pub mod expr;
+pub mod labels;
+pub mod lexer;
+pub mod lint;
+pub mod macros;
+pub mod nes;
 pub mod ops;
+pub mod output;
+pub mod parser;
+pub mod source;
+pub mod symbols;
 
-use error::CompileError;
-use std::{collections::HashMap, fmt::Display};
+use data::Data;
+use encoding::{Charset, Encoding};
+use error::{CompileError, ErrorKind, Note};
+use expr::{EvalError, Expr};
+use labels::LocalLabels;
+use lexer::{Line, Token, TokenKind};
+use lint::{Lint, LintLevels, Reporter};
+use macros::{Macro, Repeat, Repetition};
+use output::Segment;
+use parser::{Statement, Width};
+use source::Sources;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::PathBuf;
+use symbols::{SymbolKind, SymbolTable};
 
-use ops::{AddressingMode, OpCode, OPCODES_MAP, OPCODES_OP_MAP};
+use ops::{AddressingMode, CpuModel, CpuOptions, OpCode, OPCODES_MAP};
 
 pub type Result<T> = std::result::Result<T, CompileError>;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum InstructionAddress {
     None,
-    Address(u16),
+    Address(u32),
     Label(String),
 }
 
@@ -23,12 +52,13 @@ impl Display for InstructionAddress {
         match self.clone() {
             InstructionAddress::None => write!(f, "    "),
             InstructionAddress::Label(addr) => write!(f, "{}", addr),
+            InstructionAddress::Address(addr) if addr > 0xffff => write!(f, "{:06X}", addr),
             InstructionAddress::Address(addr) => write!(f, "{:04X}", addr),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Instruction {
     pub mnemonic: String,
     pub mode: AddressingMode,
@@ -53,136 +83,548 @@ impl Instruction {
             address,
         }
     }
-    pub fn from_source_line(
-        labels: &HashMap<String, u16>,
+    /// Parse a single line of source.
+    ///
+    /// `pc` is the address of the instruction, used to compute branch displacements when
+    /// the line does not start with its own address.
+    pub fn from_source_line(symbols: &SymbolTable, input: &str, pc: u32) -> Result<Self> {
+        Instruction::from_source_line_with(symbols, input, pc, &CpuOptions::default())
+    }
+    /// Parse a single line of source for `cpu`, which also gives the width of 65816 immediates.
+    pub fn from_source_line_with(
+        symbols: &SymbolTable,
         input: &str,
-    ) -> std::result::Result<Self, &'static str> {
-        let ref opcodes = *OPCODES_MAP;
-
-        let temp1: Vec<&str> = input.split("\t").filter(|s| s.len() > 0).collect();
-        let temp2: Vec<&str>;
-
-        let address: InstructionAddress = if temp1.len() > 1 {
-            temp2 = temp1[1].split(" ").filter(|s| s.len() > 0).collect();
-            // Included label OR address
-            let address_temp = temp1[0];
-            if let Ok(addr) = u16::from_str_radix(address_temp, 16) {
-                // Absolute Address
-                InstructionAddress::Address(addr)
+        pc: u32,
+        cpu: &CpuOptions,
+    ) -> Result<Self> {
+        let (lines, errors) = lexer::tokenize(input);
+        if let Some(err) = errors.into_iter().next() {
+            return Err(err);
+        }
+        let line = match &lines[..] {
+            [line] => line,
+            [] => {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    1,
+                    "missing mnemonic",
+                ))
+            }
+            [_, line, ..] => {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    line.number,
+                    "expected a single line",
+                ))
+            }
+        };
+        let statement = Statement::parse(line)?;
+        let mut reporter = Reporter::new(LintLevels::default());
+        let (instruction, fixups) =
+            Instruction::from_statement(symbols, &statement, pc, cpu, &mut reporter)?;
+        match fixups.into_iter().next() {
+            Some(fixup) => {
+                let err = fixup
+                    .expr
+                    .eval(&|name| symbols.value(name), pc)
+                    .expect_err("unresolved operands fail to evaluate");
+                Err(err.into_error(line.number, fixup.column))
+            }
+            None => Ok(instruction),
+        }
+    }
+    /// Build an instruction from a parsed statement, allowing the operand to reference symbols
+    /// that are not defined yet.
+    ///
+    /// An operand depending on an undefined symbol is sized as an absolute address unless only its
+    /// low or high byte is used or the instruction has no absolute form, or as a long address if
+    /// the instruction has one. Its bytes are left zeroed and returned as fixups with an index of
+    /// 0, to be set by the caller once the instruction has its place in the output.
+    fn from_statement(
+        symbols: &SymbolTable,
+        statement: &Statement,
+        pc: u32,
+        cpu: &CpuOptions,
+        reporter: &mut Reporter,
+    ) -> Result<(Self, Vec<Fixup>)> {
+        let line = statement.line;
+        let mnemonic = match &statement.mnemonic {
+            Some(mnemonic) => mnemonic,
+            None => {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    line,
+                    "missing mnemonic",
+                ))
+            }
+        };
+        let (name, forced) = Width::split(&mnemonic.text);
+        let codes = cpu.opcodes(name);
+        if codes.is_empty() {
+            let message = if cpu.model.is_nmos() && ops::is_illegal(name) {
+                format!(
+                    "undocumented opcode `{}` requires illegal opcodes to be allowed",
+                    name
+                )
+            } else if ops::is_mnemonic(name) {
+                format!("`{}` is not available on the {}", name, cpu.model.name())
             } else {
-                // Label
-                InstructionAddress::Label(address_temp.to_string())
+                format!("unknown mnemonic `{}`", mnemonic.text)
+            };
+            return Err(CompileError::at(
+                ErrorKind::UnknownMnemonic,
+                line,
+                mnemonic.column,
+                message,
+            ));
+        }
+        let has_mode = |mode: AddressingMode| codes.iter().any(|opcode| opcode.mode == mode);
+        let tokens = &statement.operand[..];
+        let column = tokens.first().map_or(mnemonic.column, |token| token.column);
+        let origin = match statement.address {
+            InstructionAddress::Address(addr) => addr,
+            _ => pc,
+        };
+        if has_mode(AddressingMode::ZeroPageRelative) || has_mode(AddressingMode::BlockMove) {
+            return Self::operand_pair(symbols, statement, codes[0], origin, cpu);
+        }
+
+        let (syntax, expr_tokens) =
+            OperandSyntax::parse(tokens, has_mode(AddressingMode::Accumulator));
+        let expr = if expr_tokens.is_empty() {
+            if syntax != OperandSyntax::Implied && syntax != OperandSyntax::Accumulator {
+                return Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    line,
+                    column,
+                    "missing value",
+                ));
             }
+            None
+        } else {
+            Some(Expr::parse(expr_tokens, line)?)
+        };
+        let value = match &expr {
+            Some(expr) => match expr.eval(&|name| symbols.value(name), origin) {
+                Ok(value) => Some(value),
+                Err(EvalError::Undefined(_)) => None,
+                Err(err) => return Err(err.into_error(line, column)),
+            },
+            None => None,
+        };
+        // Values that fit in a byte use zero page addressing, and so do operands depending on
+        // undefined constants once only their low or high byte is used. Other undefined
+        // values may be addresses past the zero page, so they use absolute addressing, which
+        // the fixup fills in whatever the value turns out to be.
+        let word = match value {
+            Some(value) => !(0..=0xff).contains(&value),
+            None => expr
+                .as_ref()
+                .is_some_and(|expr| expr.is_address(&|name| symbols.is_address(name))),
+        };
+        // Likewise on the 65816, values past $FFFF and literals with more than four hex digits
+        // use long addressing, which is the only one of `JML` and `JSL`. Undefined addresses
+        // may be in any bank, so they use it too.
+        let long = has_mode(AddressingMode::AbsoluteLong)
+            && (value.map_or(word, |value| value > 0xffff)
+                || !has_mode(AddressingMode::Absolute)
+                || expr_tokens.iter().any(|token| {
+                    token.kind == TokenKind::Number
+                        && token.text.starts_with('$')
+                        && token.text.len() > 5
+                }));
+        let width = forced.unwrap_or(if long {
+            Width::Long
+        } else if word {
+            Width::Absolute
         } else {
-            temp2 = temp1[0].split(" ").collect();
-            InstructionAddress::None
+            Width::ZeroPage
+        });
+        let invalid = || {
+            CompileError::at(
+                ErrorKind::InvalidAddressingMode,
+                line,
+                column,
+                "invalid addressing mode for instruction",
+            )
+        };
+        // Mode of the zero page, absolute or long `modes` of an operand with the width given.
+        // A width chosen from the value falls back to absolute addressing when the instruction
+        // lacks the mode, as `LDA value,Y` has no zero page form, but a forced one can't.
+        let sized = |modes: [Option<AddressingMode>; 3]| {
+            let index = match width {
+                Width::ZeroPage => 0,
+                Width::Absolute => 1,
+                Width::Long => 2,
+            };
+            match &modes[index] {
+                Some(mode) if forced.is_some() || has_mode(mode.clone()) => Ok(mode.clone()),
+                // Without an absolute mode, as for `STX value,Y`, the zero page mode is used and
+                // the value checked once known.
+                _ if forced.is_none() => match (&modes[0], &modes[1]) {
+                    (Some(zero_page), Some(absolute))
+                        if !has_mode(absolute.clone()) && has_mode(zero_page.clone()) =>
+                    {
+                        Ok(zero_page.clone())
+                    }
+                    (_, absolute) => Ok(absolute.clone().unwrap()),
+                },
+                _ => Err(invalid()),
+            }
         };
 
-        let mnemonic = temp2[0];
-        let operand = if temp2.len() == 1 {
-            ""
+        let branch = [AddressingMode::Relative, AddressingMode::RelativeLong]
+            .iter()
+            .find(|mode| has_mode((*mode).clone()))
+            .cloned();
+        if let Some(width) = forced {
+            if branch.is_some()
+                || !matches!(
+                    syntax,
+                    OperandSyntax::Direct | OperandSyntax::IndexedX | OperandSyntax::IndexedY
+                )
+            {
+                return Err(CompileError::at(
+                    ErrorKind::InvalidAddressingMode,
+                    line,
+                    mnemonic.column,
+                    format!(
+                        "`{}` only applies to an address, indexed or not",
+                        width.suffix()
+                    ),
+                ));
+            }
+        }
+        let mode = if let Some(mode) = branch {
+            if syntax != OperandSyntax::Direct {
+                return Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    line,
+                    column,
+                    "expected a branch target",
+                ));
+            }
+            mode
         } else {
-            if temp2[1].len() > 0 {
-                temp2[1]
-            } else {
-                ""
-            }
-        };
-
-        let (operands, mode) = if operand.len() > 0 {
-            if &operand[0..1] == "(" {
-                // Indirect Addressing
-                if &operand[(operand.len() - 1)..] == "Y" {
-                    // Indirect Y
-                    let val_t = &operand[1..(operand.len() - 3)];
-                    let (val, _) = get_bytes_from_asm(labels, val_t)?;
-                    (val, AddressingMode::IndirectY)
-                } else if &operand[(operand.len() - 2)..] == "X)" {
-                    // Indirect X
-                    let val_t = &operand[1..(operand.len() - 3)];
-                    let (val, _) = get_bytes_from_asm(labels, val_t)?;
-                    (val, AddressingMode::IndirectX)
-                } else {
-                    // Indirect
-                    let val_t = &operand[1..(operand.len() - 1)];
-                    let (val, _) = get_bytes_from_asm(labels, val_t)?;
-                    (val, AddressingMode::Indirect)
+            match syntax {
+                OperandSyntax::Implied if has_mode(AddressingMode::Accumulator) => {
+                    AddressingMode::Accumulator
                 }
+                OperandSyntax::Implied => AddressingMode::Implied,
+                OperandSyntax::Accumulator => AddressingMode::Accumulator,
+                OperandSyntax::Immediate => AddressingMode::Immediate,
+                OperandSyntax::Direct => sized([
+                    Some(AddressingMode::ZeroPage),
+                    Some(AddressingMode::Absolute),
+                    Some(AddressingMode::AbsoluteLong),
+                ])?,
+                OperandSyntax::IndexedX => sized([
+                    Some(AddressingMode::ZeroPageX),
+                    Some(AddressingMode::AbsoluteX),
+                    Some(AddressingMode::AbsoluteLongX),
+                ])?,
+                OperandSyntax::IndexedY => sized([
+                    Some(AddressingMode::ZeroPageY),
+                    Some(AddressingMode::AbsoluteY),
+                    None,
+                ])?,
+                OperandSyntax::Indirect if has_mode(AddressingMode::Indirect) => {
+                    AddressingMode::Indirect
+                }
+                OperandSyntax::Indirect if has_mode(AddressingMode::ZeroPageIndirect) => {
+                    AddressingMode::ZeroPageIndirect
+                }
+                // Rather than reading the parentheses as grouping, which would drop the
+                // indirection the code asks for.
+                OperandSyntax::Indirect => {
+                    return Err(CompileError::at(
+                        ErrorKind::InvalidAddressingMode,
+                        line,
+                        column,
+                        format!(
+                            "`{}` has no indirect mode on the {}",
+                            name,
+                            cpu.model.name()
+                        ),
+                    ))
+                }
+                OperandSyntax::IndirectX if has_mode(AddressingMode::AbsoluteIndirectX) => {
+                    AddressingMode::AbsoluteIndirectX
+                }
+                OperandSyntax::IndirectX => AddressingMode::IndirectX,
+                OperandSyntax::IndirectY => AddressingMode::IndirectY,
+                OperandSyntax::IndirectLong if has_mode(AddressingMode::AbsoluteIndirectLong) => {
+                    AddressingMode::AbsoluteIndirectLong
+                }
+                OperandSyntax::IndirectLong => AddressingMode::DirectIndirectLong,
+                OperandSyntax::IndirectLongY => AddressingMode::DirectIndirectLongY,
+                OperandSyntax::StackRelative => AddressingMode::StackRelative,
+                OperandSyntax::StackRelativeIndirectY => AddressingMode::StackRelativeIndirectY,
+            }
+        };
+        let opcode = match codes.iter().find(|opcode| opcode.mode == mode) {
+            Some(opcode) => opcode,
+            None => return Err(invalid()),
+        };
+        let len = cpu.operand_len(opcode);
+        let kind = match (&mode, len) {
+            (AddressingMode::Relative, _) => FixupKind::Relative,
+            (AddressingMode::RelativeLong, _) => FixupKind::RelativeLong,
+            (AddressingMode::Immediate, _) => FixupKind::Immediate,
+            (_, 3) => FixupKind::Long,
+            (_, 2) => FixupKind::Word,
+            _ => FixupKind::Byte,
+        };
+        let operands = match value {
+            Some(target) if kind == FixupKind::Relative || kind == FixupKind::RelativeLong => {
+                let next = origin + 1 + len as u32;
+                branch_offset(&kind, next, target, cpu, line, column)?
+            }
+            Some(value) if kind == FixupKind::Immediate => {
+                immediate(value, len, column, line, reporter)?
+            }
+            Some(value) => kind.encode(bank_address(&kind, origin, value), column, line)?,
+            None => vec![0; len],
+        };
+        let fixups = match (expr, value) {
+            (Some(expr), None) => vec![Fixup {
+                index: 0,
+                offset: 0,
+                kind,
+                expr,
+                line,
+                column,
+                notes: vec![],
+                scope: symbols.scope.clone(),
+            }],
+            _ => vec![],
+        };
+        Ok((
+            Instruction::new(
+                name.to_string(),
+                mode,
+                opcode.code,
+                operands,
+                statement.address.clone(),
+            ),
+            fixups,
+        ))
+    }
+    /// Build an instruction written with two operands: a `BBR` or `BBS` testing a bit of a
+    /// zero page byte before branching, or a `MVN` or `MVP` block move between two banks.
+    fn operand_pair(
+        symbols: &SymbolTable,
+        statement: &Statement,
+        opcode: &OpCode,
+        origin: u32,
+        cpu: &CpuOptions,
+    ) -> Result<(Self, Vec<Fixup>)> {
+        let line = statement.line;
+        let mnemonic = statement
+            .mnemonic
+            .as_ref()
+            .expect("instruction statements have a mnemonic");
+        // Offset in the operand bytes and encoding of each operand, in source order.
+        let (layout, expected) = match opcode.mode {
+            AddressingMode::ZeroPageRelative => (
+                [(0, FixupKind::Byte), (1, FixupKind::Relative)],
+                "expected a zero page address and a branch target",
+            ),
+            // Block moves are written source first, but encode the destination first.
+            _ => (
+                [(1, FixupKind::Byte), (0, FixupKind::Byte)],
+                "expected a source and a destination bank",
+            ),
+        };
+        let arguments = data::split_arguments(&statement.operand);
+        if arguments.len() != 2 || arguments.iter().any(|argument| argument.is_empty()) {
+            let column = statement
+                .operand
+                .first()
+                .map_or(mnemonic.column, |token| token.column);
+            return Err(CompileError::at(
+                ErrorKind::SyntaxError,
+                line,
+                column,
+                expected,
+            ));
+        }
+        let mut operands = vec![0; 2];
+        let mut fixups = vec![];
+        for (argument, (offset, kind)) in arguments.into_iter().zip(layout) {
+            // Banks are often written as immediates, `MVN #$01,#$02`.
+            let argument = match argument {
+                [hash, rest @ ..]
+                    if hash.is_symbol("#") && opcode.mode == AddressingMode::BlockMove =>
+                {
+                    rest
+                }
+                _ => argument,
+            };
+            let column = argument
+                .first()
+                .map_or(mnemonic.column, |token| token.column);
+            let expr = Expr::parse(argument, line)?;
+            let value = match expr.eval(&|name| symbols.value(name), origin) {
+                Ok(value) => value,
+                Err(EvalError::Undefined(_)) => {
+                    fixups.push(Fixup {
+                        index: 0,
+                        offset,
+                        kind,
+                        expr,
+                        line,
+                        column,
+                        notes: vec![],
+                        scope: symbols.scope.clone(),
+                    });
+                    continue;
+                }
+                Err(err) => return Err(err.into_error(line, column)),
+            };
+            operands[offset] = if kind == FixupKind::Relative {
+                branch_offset(&kind, origin + 3, value, cpu, line, column)?[0]
             } else {
-                let val_t = &operand[0..];
-                let last_t = &val_t[(val_t.len() - 1)..];
-                match last_t {
-                    "X" | "Y" => {
-                        // Y
-                        let is_x = last_t == "X";
-                        let (val, _) = get_bytes_from_asm(labels, &val_t[..(val_t.len() - 2)])?;
-                        if val.len() == 2 {
-                            // Absolute X/Y
-                            (
-                                val,
-                                if is_x {
-                                    AddressingMode::AbsoluteX
-                                } else {
-                                    AddressingMode::AbsoluteY
-                                },
-                            )
-                        } else {
-                            // ZeroPage X/Y
-                            (
-                                val,
-                                if is_x {
-                                    AddressingMode::ZeroPageX
-                                } else {
-                                    AddressingMode::ZeroPageY
-                                },
-                            )
-                        }
+                kind.encode(value, column, line)?[0]
+            };
+        }
+        Ok((
+            Instruction::new(
+                mnemonic.text.clone(),
+                opcode.mode.clone(),
+                opcode.code,
+                operands,
+                statement.address.clone(),
+            ),
+            fixups,
+        ))
+    }
+    /// Target of a branch, once the instruction has its address.
+    fn branch_target(&self) -> Option<u32> {
+        let origin = match self.address {
+            InstructionAddress::Address(origin) => origin,
+            _ => return None,
+        };
+        match self.mode {
+            AddressingMode::Relative => {
+                Some(branch_target(origin, 2, self.operands[0] as i8 as u16))
+            }
+            AddressingMode::ZeroPageRelative => {
+                Some(branch_target(origin, 3, self.operands[1] as i8 as u16))
+            }
+            AddressingMode::RelativeLong => {
+                let offset = u16::from_le_bytes([self.operands[0], self.operands[1]]);
+                Some(branch_target(origin, 3, offset))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Addressing syntax of an operand, before the width of its value is known.
+#[derive(Clone, Debug, PartialEq)]
+enum OperandSyntax {
+    Implied,
+    /// `A`
+    Accumulator,
+    /// `#value`
+    Immediate,
+    /// `value`
+    Direct,
+    /// `value,X`
+    IndexedX,
+    /// `value,Y`
+    IndexedY,
+    /// `(value)`
+    Indirect,
+    /// `(value,X)`
+    IndirectX,
+    /// `(value),Y`
+    IndirectY,
+    /// `[value]`
+    IndirectLong,
+    /// `[value],Y`
+    IndirectLongY,
+    /// `value,S`
+    StackRelative,
+    /// `(value,S),Y`
+    StackRelativeIndirectY,
+}
+
+impl OperandSyntax {
+    /// Split operand tokens into their addressing syntax and the tokens of the value.
+    ///
+    /// Parentheses around the whole operand always mean indirection, even for an instruction
+    /// without an indirect mode. `A` only names the accumulator if the instruction has an
+    /// accumulator mode.
+    fn parse(tokens: &[Token], has_accumulator: bool) -> (Self, &[Token]) {
+        let is_register = |token: &Token, name: &str| {
+            token.kind == TokenKind::Identifier && token.text.eq_ignore_ascii_case(name)
+        };
+        // Split a trailing `,X` or `,Y` off the tokens.
+        let indexed = |tokens: &[Token], register: &str| -> Option<usize> {
+            match tokens {
+                [.., comma, index] if comma.is_symbol(",") && is_register(index, register) => {
+                    Some(tokens.len() - 2)
+                }
+                _ => None,
+            }
+        };
+        if tokens.is_empty() {
+            return (OperandSyntax::Implied, tokens);
+        }
+        if let [register] = tokens {
+            if has_accumulator && is_register(register, "A") {
+                return (OperandSyntax::Accumulator, &[]);
+            }
+        }
+        if tokens[0].is_symbol("#") {
+            return (OperandSyntax::Immediate, &tokens[1..]);
+        }
+        if tokens[0].is_symbol("(") {
+            let mut depth = 0;
+            let close = tokens.iter().position(|token| {
+                if token.is_symbol("(") {
+                    depth += 1;
+                } else if token.is_symbol(")") {
+                    depth -= 1;
+                }
+                depth == 0
+            });
+            if let Some(close) = close {
+                let inner = &tokens[1..close];
+                if close == tokens.len() - 1 {
+                    if let Some(end) = indexed(inner, "X") {
+                        return (OperandSyntax::IndirectX, &inner[..end]);
                     }
-                    _ => {
-                        let (val, absolute) = get_bytes_from_asm(labels, val_t)?;
-                        if absolute {
-                            if val.len() == 2 {
-                                // Absolute
-                                (val, AddressingMode::Absolute)
-                            } else {
-                                (val, AddressingMode::ZeroPage)
-                            }
-                        } else {
-                            (val, AddressingMode::Immediate)
-                        }
+                    return (OperandSyntax::Indirect, inner);
+                } else if indexed(tokens, "Y") == Some(close + 1) {
+                    if let Some(end) = indexed(inner, "S") {
+                        return (OperandSyntax::StackRelativeIndirectY, &inner[..end]);
                     }
+                    return (OperandSyntax::IndirectY, inner);
                 }
             }
-        } else {
-            (vec![], AddressingMode::Implied)
-        };
-        let code: Option<u8> = if let Some(codes) = opcodes.get(mnemonic) {
-            let mut c: Option<u8> = None;
-            for opcode in codes {
-                if opcode.mode == mode {
-                    c = Some(opcode.code);
-                    break;
-                } else if opcode.mode == AddressingMode::Relative
-                    && mode == AddressingMode::ZeroPage
-                {
-                    c = Some(opcode.code);
-                    break;
+        }
+        if tokens[0].is_symbol("[") {
+            if let Some(close) = tokens.iter().position(|token| token.is_symbol("]")) {
+                let inner = &tokens[1..close];
+                if close == tokens.len() - 1 {
+                    return (OperandSyntax::IndirectLong, inner);
+                } else if indexed(tokens, "Y") == Some(close + 1) {
+                    return (OperandSyntax::IndirectLongY, inner);
                 }
             }
-            c
-        } else {
-            None
-        };
-        if let Some(op) = code {
-            Ok(Instruction::new(
-                mnemonic.to_string(),
-                mode,
-                op,
-                operands,
-                address,
-            ))
+        }
+        if let Some(end) = indexed(tokens, "S") {
+            return (OperandSyntax::StackRelative, &tokens[..end]);
+        }
+        if let Some(end) = indexed(tokens, "X") {
+            (OperandSyntax::IndexedX, &tokens[..end])
+        } else if let Some(end) = indexed(tokens, "Y") {
+            (OperandSyntax::IndexedY, &tokens[..end])
         } else {
-            Err("no opcode found")
+            (OperandSyntax::Direct, tokens)
         }
     }
 }
@@ -200,11 +642,11 @@ impl From<OpCode> for Instruction {
     }
 }
 
-impl Into<(InstructionAddress, Vec<u8>)> for Instruction {
-    fn into(mut self) -> (InstructionAddress, Vec<u8>) {
-        let mut result = vec![self.code];
-        result.append(&mut self.operands);
-        (self.address, result)
+impl From<Instruction> for (InstructionAddress, Vec<u8>) {
+    fn from(mut instruction: Instruction) -> Self {
+        let mut result = vec![instruction.code];
+        result.append(&mut instruction.operands);
+        (instruction.address, result)
     }
 }
 
@@ -215,284 +657,2698 @@ impl Display for Instruction {
     }
 }
 
-fn get_bytes_from_asm(
-    labels: &HashMap<String, u16>,
-    input: &str,
-) -> std::result::Result<(Vec<u8>, bool), &'static str> {
-    if &input[0..1] == "$" {
-        // Address
-        let val_t = &input[1..];
-        if val_t.len() == 4 {
-            // u16
-            if let Ok(val) = u16::from_str_radix(val_t, 16) {
-                Ok((val.to_le_bytes().to_vec(), true))
-            } else {
-                Err("error converting value to u16")
-            }
-        } else {
-            if let Ok(val) = u8::from_str_radix(val_t, 16) {
-                Ok((vec![val], true))
-            } else {
-                Err("error converting value to u8")
-            }
-        }
-    } else if &input[0..1] == "#" {
-        // Numeric Value
-        match &input[1..2] {
-            "$" => {
-                // Hex
-                let val_t = &input[2..];
-                if val_t.len() == 2 {
-                    // u8
-                    if let Ok(val) = u8::from_str_radix(val_t, 16) {
-                        Ok((vec![val], false))
-                    } else {
-                        Err("error converting value to u8")
-                    }
-                } else {
-                    if let Ok(val) = u16::from_str_radix(val_t, 16) {
-                        Ok((val.to_le_bytes().to_vec(), false))
-                    } else {
-                        Err("error converting value to u16")
-                    }
-                }
-            }
-            "%" => {
-                // Binary
-                let val_t = &input[2..];
-                if val_t.len() == 8 {
-                    // u16
-                    if let Ok(val) = u16::from_str_radix(val_t, 2) {
-                        Ok((val.to_le_bytes().to_vec(), false))
-                    } else {
-                        Err("error converting value to u16")
-                    }
-                } else {
-                    // u8
-                    if let Ok(val) = u8::from_str_radix(val_t, 2) {
-                        Ok((vec![val], false))
-                    } else {
-                        Err("error converting value to u8")
-                    }
-                }
-            }
-            _ => {
-                // Decimal
-                let val_t = &input[1..];
-                if let Ok(byte) = u8::from_str_radix(val_t, 10) {
-                    Ok((vec![byte], false))
-                } else {
-                    if let Ok(val) = u16::from_str_radix(val_t, 10) {
-                        Ok((val.to_le_bytes().to_vec(), false))
-                    } else {
-                        Err("error converting value to u16")
-                    }
-                }
-            }
-        }
+/// 16-bit operand `kind` of an instruction at `origin` for the address `value`. The 65816
+/// takes these addresses in the bank of the program or of the data, so an address in the bank
+/// of the instruction is written without its bank.
+fn bank_address(kind: &FixupKind, origin: u32, value: i64) -> i64 {
+    if *kind == FixupKind::Word && value >> 16 == origin as i64 >> 16 {
+        value & 0xffff
     } else {
-        // Label?
-        if let Some(label) = labels.get(&input.to_string()) {
-            Ok((label.to_le_bytes().to_vec(), true))
-        } else {
-            if cfg!(debug_assertions) {
-                println!("DEBUG ON ERROR PREFIX: {}", input);
-            }
-            Err("invalid value prefix")
-        }
+        value
     }
 }
 
-impl Into<String> for Instruction {
-    fn into(self) -> String {
-        match self.operands.len() {
-            2 => {
-                // u16
-                let value = u16::from_le_bytes([self.operands[0], self.operands[1]]);
-                format!(
-                    "{} {}",
-                    self.mnemonic,
-                    match self.mode {
-                        AddressingMode::Absolute => format!("${:04X}", value),
-                        AddressingMode::AbsoluteX => format!("${:04X},X", value),
-                        AddressingMode::AbsoluteY => format!("${:04X},Y", value),
-                        AddressingMode::Indirect => format!("(${:04X})", value),
-                        _ => panic!("mismatched addressing mode and operand length"),
-                    }
-                )
-            }
-            1 => {
-                let value = self.operands[0];
+/// Compute the displacement of a branch to `target`, relative to `next`, the address of the
+/// following instruction.
+fn relative_offset(next: u32, target: u32) -> std::result::Result<u8, &'static str> {
+    let offset = target as i64 - next as i64;
+    if (-128..=127).contains(&offset) {
+        Ok(offset as i8 as u8)
+    } else {
+        Err("branch out of range")
+    }
+}
+
+/// Compute the 16-bit displacement of a long branch to `target`, relative to `next`.
+fn relative_long_offset(next: u32, target: u32) -> std::result::Result<u16, &'static str> {
+    // Long branches wrap around within their bank, so they reach any address in it.
+    if next & 0xff0000 == target & 0xff0000 {
+        Ok((target as u16).wrapping_sub(next as u16))
+    } else {
+        Err("branch to another bank")
+    }
+}
+
+/// Encode the displacement of a `Relative` or `RelativeLong` branch to `target`, relative to
+/// `next`, the address of the following instruction.
+fn branch_offset(
+    kind: &FixupKind,
+    next: u32,
+    target: i64,
+    cpu: &CpuOptions,
+    line: usize,
+    column: usize,
+) -> Result<Vec<u8>> {
+    if !(0..cpu.model.address_space() as i64).contains(&target) {
+        return Err(CompileError::at(
+            ErrorKind::ValueOutOfRange,
+            line,
+            column,
+            "value out of range",
+        ));
+    }
+    let encoded = if *kind == FixupKind::Relative {
+        relative_offset(next, target as u32).map(|offset| vec![offset])
+    } else {
+        relative_long_offset(next, target as u32).map(|offset| offset.to_le_bytes().to_vec())
+    };
+    encoded.map_err(|err| CompileError::at(ErrorKind::BranchOutOfRange, line, column, err))
+}
+
+/// Resolve the target address of a branch of `len` bytes at `origin` with the encoded
+/// displacement `offset`.
+fn branch_target(origin: u32, len: u16, offset: u16) -> u32 {
+    // The program counter wraps around within the bank of the instruction, so the next
+    // instruction may be at the start of the same bank.
+    (origin & 0xff0000) | (origin as u16).wrapping_add(len).wrapping_add(offset) as u32
+}
+
+/// Encode an immediate operand of `len` bytes, cut to fit if the `truncated-immediate` lint
+/// allows.
+fn immediate(
+    value: i64,
+    len: usize,
+    column: usize,
+    line: usize,
+    reporter: &mut Reporter,
+) -> Result<Vec<u8>> {
+    let (range, message) = if len == 2 {
+        (-0x8000..=0xffff, "immediate value does not fit in a word")
+    } else {
+        (-0x80..=0xff, "immediate value does not fit in a byte")
+    };
+    if !range.contains(&value) {
+        reporter.report(Lint::TruncatedImmediate, line, Some(column), message)?;
+    }
+    Ok(value.to_le_bytes()[..len].to_vec())
+}
+
+impl From<Instruction> for String {
+    fn from(instruction: Instruction) -> Self {
+        (&instruction).into()
+    }
+}
+
+impl From<&Instruction> for String {
+    fn from(instruction: &Instruction) -> Self {
+        instruction.format(&DecompileOptions::default())
+    }
+}
+
+/// How disassembled instructions are written.
+#[derive(Clone, Debug, Default)]
+pub struct DecompileOptions {
+    /// Write the accumulator form of shifts and rotates as `ASL A` rather than `ASL`.
+    pub accumulator_operand: bool,
+    /// Instructions to decode, other bytes are shown as data.
+    pub cpu: CpuOptions,
+}
+
+impl Instruction {
+    /// Write the instruction as source, without its address.
+    pub fn format(&self, options: &DecompileOptions) -> String {
+        let instruction = self;
+        match instruction.operands.len() {
+            3 => {
+                // 24-bit address
+                let value = u32::from_le_bytes([
+                    instruction.operands[0],
+                    instruction.operands[1],
+                    instruction.operands[2],
+                    0,
+                ]);
                 format!(
                     "{} {}",
-                    self.mnemonic,
-                    match self.mode {
-                        AddressingMode::Immediate => format!("#${:02X}", value),
-                        AddressingMode::ZeroPage => format!("${:02X}", value),
-                        AddressingMode::ZeroPageX => format!("${:02X},X", value),
-                        AddressingMode::ZeroPageY => format!("${:02X},Y", value),
-                        AddressingMode::IndirectX => format!("(${:02X},X)", value),
-                        AddressingMode::IndirectY => format!("(${:02X}),Y", value),
-                        _ => panic!("mismatched addressing mode and operand length"),
+                    instruction.mnemonic,
+                    match instruction.mode {
+                        AddressingMode::AbsoluteLong => format!("${:06X}", value),
+                        AddressingMode::AbsoluteLongX => format!("${:06X},X", value),
+                        _ => panic!(
+                            "0x{:02X} mismatched addressing mode and operand length 3: {:?} - {:?}",
+                            instruction.code,
+                            instruction.mode,
+                            instruction.operands.clone()
+                        ),
                     }
                 )
             }
-            0 => format!("{}", self.mnemonic),
-            _ => panic!("invalid operand count"),
-        }
-    }
-}
-
-impl Into<String> for &Instruction {
-    fn into(self) -> String {
-        match self.operands.len() {
             2 => {
                 // u16
-                let value = u16::from_le_bytes([self.operands[0], self.operands[1]]);
+                let value = u16::from_le_bytes([instruction.operands[0], instruction.operands[1]]);
                 format!(
                     "{} {}",
-                    self.mnemonic,
-                    match self.mode {
+                    instruction.mnemonic,
+                    match instruction.mode {
+                        // Addresses in the zero page would be assembled as zero page ones.
+                        AddressingMode::Absolute
+                        | AddressingMode::AbsoluteX
+                        | AddressingMode::AbsoluteY
+                            if value <= 0xff =>
+                        {
+                            let index = match instruction.mode {
+                                AddressingMode::AbsoluteX => ",X",
+                                AddressingMode::AbsoluteY => ",Y",
+                                _ => "",
+                            };
+                            format!("a:${:04X}{}", value, index)
+                        }
                         AddressingMode::Absolute => format!("${:04X}", value),
                         AddressingMode::AbsoluteX => format!("${:04X},X", value),
                         AddressingMode::AbsoluteY => format!("${:04X},Y", value),
                         AddressingMode::Indirect => format!("(${:04X})", value),
+                        AddressingMode::AbsoluteIndirectX => format!("(${:04X},X)", value),
+                        AddressingMode::AbsoluteIndirectLong => format!("[${:04X}]", value),
+                        AddressingMode::Immediate => format!("#${:04X}", value),
+                        AddressingMode::RelativeLong => match instruction.branch_target() {
+                            Some(target) => format!("${}", InstructionAddress::Address(target)),
+                            None => format!("${:04X}", value),
+                        },
+                        // Written source bank first, encoded destination first.
+                        AddressingMode::BlockMove => format!(
+                            "${:02X},${:02X}",
+                            instruction.operands[1], instruction.operands[0]
+                        ),
+                        AddressingMode::ZeroPageRelative => {
+                            let [zero_page, offset] =
+                                [instruction.operands[0], instruction.operands[1]];
+                            match instruction.branch_target() {
+                                Some(target) => format!(
+                                    "${:02X},${}",
+                                    zero_page,
+                                    InstructionAddress::Address(target)
+                                ),
+                                None => format!("${:02X},${:02X}", zero_page, offset),
+                            }
+                        }
                         _ => panic!(
                             "0x{:02X} mismatched addressing mode and operand length 2: {:?} - {:?}",
-                            self.code,
-                            self.mode,
-                            self.operands.clone()
+                            instruction.code,
+                            instruction.mode,
+                            instruction.operands.clone()
                         ),
                     }
                 )
             }
             1 => {
-                let value = self.operands[0];
+                let value = instruction.operands[0];
                 format!(
                     "{} {}",
-                    self.mnemonic,
-                    match self.mode {
+                    instruction.mnemonic,
+                    match instruction.mode {
                         AddressingMode::Immediate => format!("#${:02X}", value),
                         AddressingMode::ZeroPage => format!("${:02X}", value),
                         AddressingMode::ZeroPageX => format!("${:02X},X", value),
                         AddressingMode::ZeroPageY => format!("${:02X},Y", value),
                         AddressingMode::IndirectX => format!("(${:02X},X)", value),
                         AddressingMode::IndirectY => format!("(${:02X}),Y", value),
-                        AddressingMode::Relative => format!("${:02X}", value),
+                        AddressingMode::ZeroPageIndirect => format!("(${:02X})", value),
+                        AddressingMode::DirectIndirectLong => format!("[${:02X}]", value),
+                        AddressingMode::DirectIndirectLongY => format!("[${:02X}],Y", value),
+                        AddressingMode::StackRelative => format!("${:02X},S", value),
+                        AddressingMode::StackRelativeIndirectY => {
+                            format!("(${:02X},S),Y", value)
+                        }
+                        AddressingMode::Relative => match instruction.branch_target() {
+                            Some(target) => format!("${}", InstructionAddress::Address(target)),
+                            None => format!("${:02X}", value),
+                        },
                         _ => panic!(
                             "mismatched addressing mode and operand length 1: {:?} - {:?}",
-                            self.mode,
-                            self.operands.clone()
+                            instruction.mode,
+                            instruction.operands.clone()
                         ),
                     }
                 )
             }
-            0 => format!("{}", self.mnemonic),
+            0 if instruction.mode == AddressingMode::Accumulator && options.accumulator_operand => {
+                format!("{} A", instruction.mnemonic)
+            }
+            0 => instruction.mnemonic.clone(),
             _ => panic!("invalid operand count"),
         }
     }
 }
 
+/// A piece of compiled output.
+#[derive(Clone, Debug)]
+pub enum Item {
+    Instruction(Instruction),
+    Data(Data),
+}
+
+impl Item {
+    pub fn address(&self) -> &InstructionAddress {
+        match self {
+            Item::Instruction(instruction) => &instruction.address,
+            Item::Data(data) => &data.address,
+        }
+    }
+    fn address_mut(&mut self) -> &mut InstructionAddress {
+        match self {
+            Item::Instruction(instruction) => &mut instruction.address,
+            Item::Data(data) => &mut data.address,
+        }
+    }
+    /// Number of bytes the item occupies in memory.
+    pub fn size(&self) -> usize {
+        match self {
+            Item::Instruction(instruction) => instruction.operands.len() + 1,
+            Item::Data(data) => data.bytes.len(),
+        }
+    }
+}
+
+impl From<Item> for (InstructionAddress, Vec<u8>) {
+    fn from(item: Item) -> Self {
+        match item {
+            Item::Instruction(instruction) => instruction.into(),
+            Item::Data(data) => (data.address, data.bytes),
+        }
+    }
+}
+
+impl Display for Item {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Item::Instruction(instruction) => write!(f, "{}", instruction),
+            Item::Data(data) => write!(f, "{}", data),
+        }
+    }
+}
+
 pub struct Asm6502 {
     pub input: String,
-    pub instructions: Vec<Instruction>,
-    memory_start: u16,
+    pub items: Vec<Item>,
+    /// Symbols defined by the last compilation.
+    pub symbols: SymbolTable,
+    /// Name of the source file, used in errors.
+    pub file: Option<String>,
+    /// Instructions accepted in the source.
+    pub cpu: CpuOptions,
+    /// Levels of the lints checked while compiling.
+    pub lints: LintLevels,
+    /// Warnings reported by the last compilation.
+    pub warnings: Vec<CompileError>,
+    /// Predefine the NES registers of [`nes::REGISTERS`], such as `PPUCTRL`.
+    pub nes_symbols: bool,
+    /// Directories searched by `.include` and `.incbin`, after the directory of the including
+    /// file.
+    pub include_paths: Vec<PathBuf>,
+    memory_start: u32,
 }
 
 impl Asm6502 {
-    pub fn new(data: String, memory_start: u16) -> Self {
+    pub fn new(data: String, memory_start: u32) -> Self {
         Asm6502 {
-            input: data.replace("\r\n", "\n").trim().to_string(),
-            instructions: vec![],
+            input: data.replace("\r\n", "\n"),
+            items: vec![],
+            symbols: SymbolTable::new(),
+            file: None,
+            cpu: CpuOptions::default(),
+            lints: LintLevels::default(),
+            warnings: vec![],
+            nes_symbols: false,
+            include_paths: vec![],
             memory_start,
         }
     }
-    pub fn decompile(input: Vec<u8>, memory_start: u16) -> Vec<String> {
-        let ref opcodes = *OPCODES_OP_MAP;
+    pub fn decompile(input: Vec<u8>, memory_start: u32) -> Vec<String> {
+        Asm6502::decompile_with(input, memory_start, &DecompileOptions::default())
+    }
+    /// Disassemble `input` loaded at `memory_start`, formatted according to `options`.
+    pub fn decompile_with(
+        input: Vec<u8>,
+        memory_start: u32,
+        options: &DecompileOptions,
+    ) -> Vec<String> {
         let mut result = vec![];
+        // The 65816 register widths start from the options and follow `REP` and `SEP`.
+        let mut cpu = options.cpu.clone();
         let mut i: usize = 0;
-        loop {
-            if i >= input.len() {
-                break;
-            }
+        while i < input.len() {
             let b = input[i];
-            if let Some(opcode) = opcodes.get(&b) {
-                let code = opcode.code;
-                let mnemonic = opcode.mnemonic.to_string();
-                let address = InstructionAddress::Address(memory_start + i as u16);
-                let operands: Vec<u8> = input[i + 1..opcode.len as usize + i].to_vec();
-                let instruction =
-                    Instruction::new(mnemonic, opcode.mode.clone(), code, operands, address);
-                result.push(format!("{}", instruction.to_string()));
-                if i + opcode.len as usize > input.len() - 1 {
-                    break;
+            let address = InstructionAddress::Address(memory_start + i as u32);
+            let len = cpu.decode(b).map(|opcode| 1 + cpu.operand_len(opcode));
+            match cpu.decode(b).zip(len) {
+                Some((opcode, len)) if i + len <= input.len() => {
+                    // Undocumented opcodes are written without their `*` once they are allowed.
+                    let mnemonic = opcode.mnemonic.trim_start_matches('*').to_string();
+                    let operands: Vec<u8> = input[i + 1..i + len].to_vec();
+                    let instruction =
+                        Instruction::new(mnemonic, opcode.mode.clone(), b, operands, address);
+                    result.push(format!(
+                        "{}\t{}",
+                        instruction.address,
+                        instruction.format(options)
+                    ));
+                    i += len;
+                    if cpu.model == CpuModel::Wdc65816 {
+                        // Write the widths the next instructions are decoded with as directives,
+                        // so that the output assembles back to the same bytes.
+                        let next = InstructionAddress::Address(memory_start + i as u32);
+                        for hint in track_register_widths(&mut cpu, &instruction) {
+                            result.push(format!("{}\t{}", next, hint));
+                        }
+                    }
+                }
+                // Bytes that are not an instruction of this CPU are shown as data.
+                _ => {
+                    result.push(format!("{}\t.byte ${:02X}", address, b));
+                    i += 1;
                 }
-                i += opcode.len as usize;
-            } else {
-                i += 1;
             }
         }
         result
     }
-    pub fn compile(&mut self) -> Result<Vec<Instruction>> {
-        let mut result = vec![];
-        let mut labels: HashMap<String, u16> = HashMap::new();
-        let mut line_number: usize = 1;
-        let mut first_addr: u16 = self.memory_start;
-        let mut current_addr: u16 = first_addr;
-        for line in self.input.split("\n") {
-            match Instruction::from_source_line(&labels, line) {
-                Ok(mut instruction) => {
-                    match instruction.address.clone() {
-                        InstructionAddress::Label(label) => {
-                            labels.insert(label.clone(), current_addr);
-                            instruction.address = InstructionAddress::Address(current_addr);
-                        }
-                        InstructionAddress::None => {
-                            instruction.address = InstructionAddress::Address(current_addr);
-                        }
-                        InstructionAddress::Address(adr) => {
-                            if line_number == 1 {
-                                first_addr = adr;
-                            }
+    /// Group the compiled items into segments of contiguous memory.
+    pub fn segments(&self) -> Vec<Segment> {
+        output::segments(&self.items)
+    }
+    /// Lay the compiled items out as a raw binary, filling gaps between origins with `pad`.
+    pub fn to_binary(&self, pad: u8) -> Vec<u8> {
+        output::to_binary(&self.segments(), pad)
+    }
+    /// Compile the input, reporting every error found rather than stopping at the first.
+    pub fn compile(&mut self) -> std::result::Result<Vec<Item>, Vec<CompileError>> {
+        let mut assembler = Assembler::new(self.memory_start, self.cpu.clone(), self.lints.clone());
+        assembler.include_paths = self.include_paths.clone();
+        let (lines, mut errors) = assembler.sources.add(
+            self.file.clone(),
+            self.file.as_ref().map(PathBuf::from),
+            self.input.clone(),
+            None,
+        );
+        if self.nes_symbols {
+            for (name, value) in nes::REGISTERS.iter() {
+                // Predefined symbols have no line.
+                assembler
+                    .symbols
+                    .define(name, *value, SymbolKind::Constant, 0)
+                    .expect("predefined symbols are unique");
+            }
+        }
+        for line in &lines {
+            errors.append(&mut assembler.line(line));
+        }
+        errors.append(&mut assembler.finish());
+        let mut warnings = std::mem::take(&mut assembler.reporter.warnings);
+        // Sorted while the lines are still numbered across the files, in the order they were read.
+        warnings.sort_by_key(|warning| (warning.line, warning.column));
+        errors.sort_by_key(|err| (err.line, err.column));
+        for err in errors.iter_mut().chain(warnings.iter_mut()) {
+            assembler.sources.locate_error(err);
+        }
+        self.warnings = warnings;
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.items = assembler.items.clone();
+        self.symbols = assembler.symbols;
+        Ok(assembler.items)
+    }
+}
+
+/// Follow the `REP` and `SEP` of `instruction` in the 65816 register widths of `cpu`,
+/// returning the directives giving the widths that changed.
+fn track_register_widths(cpu: &mut CpuOptions, instruction: &Instruction) -> Vec<&'static str> {
+    // `REP` clears the flags it selects, which makes the registers 16-bit.
+    let wide = match instruction.mnemonic.as_str() {
+        "REP" => true,
+        "SEP" => false,
+        _ => return vec![],
+    };
+    let flags = instruction.operands[0];
+    let mut hints = vec![];
+    if flags & 0x20 != 0 && cpu.wide_accumulator != wide {
+        cpu.wide_accumulator = wide;
+        hints.push(if wide { ".a16" } else { ".a8" });
+    }
+    if flags & 0x10 != 0 && cpu.wide_index != wide {
+        cpu.wide_index = wide;
+        hints.push(if wide { ".i16" } else { ".i8" });
+    }
+    hints
+}
+
+/// Width of a 65816 register, set by a directive.
+enum RegisterWidth {
+    Accumulator(bool),
+    Index(bool),
+}
+
+/// Register width set by `directive`, where `true` means 16 bits.
+fn register_width(directive: &str) -> Option<RegisterWidth> {
+    match directive {
+        ".a8" => Some(RegisterWidth::Accumulator(false)),
+        ".a16" => Some(RegisterWidth::Accumulator(true)),
+        ".i8" => Some(RegisterWidth::Index(false)),
+        ".i16" => Some(RegisterWidth::Index(true)),
+        _ => None,
+    }
+}
+
+/// State of a compilation in progress.
+struct Assembler {
+    items: Vec<Item>,
+    symbols: SymbolTable,
+    fixups: Vec<Fixup>,
+    /// Kept wider than an address so running past the address space can be detected.
+    current_addr: u32,
+    /// Memory written since each origin: start, end and the line setting the origin.
+    regions: Vec<(u32, u32, usize)>,
+    /// Line and column of each item, for the lints checked once the output is complete.
+    positions: Vec<(usize, usize)>,
+    /// Names used in operands, to find unused labels and names that a symbol defined further
+    /// down in an inner scope would have referred to.
+    references: Vec<Reference>,
+    cpu: CpuOptions,
+    reporter: Reporter,
+    macros: HashMap<String, Macro>,
+    /// Macro being defined, with the number of `.macro` nested in its body.
+    definition: Option<(Macro, usize)>,
+    /// `.rept` or `.for` block being recorded, with the number of blocks nested in its body.
+    repetition: Option<(Repetition, usize)>,
+    /// Number of macro expansions so far, which makes the labels of each one unique.
+    expansions: usize,
+    /// Notes locating the macro expansions in progress, outermost first.
+    trace: Vec<Note>,
+    /// Conditional blocks entered, innermost last.
+    conditions: Vec<Condition>,
+    /// Symbols that `.ifdef` or `.ifndef` found undefined: directive, name, scope, line and
+    /// column.
+    probes: Vec<(String, String, String, usize, usize)>,
+    /// Files read so far, which number the lines.
+    sources: Sources,
+    /// Directories searched by `.include` and `.incbin`.
+    include_paths: Vec<PathBuf>,
+    labels: LocalLabels,
+    /// Scopes opened by `.proc` or `.scope`, innermost last.
+    scopes: Vec<Scope>,
+    charset: Charset,
+}
+
+/// A name used in an operand.
+struct Reference {
+    name: String,
+    /// Scope the name was looked up from.
+    scope: String,
+    /// Symbol the name referred to when it was used, if it was defined already.
+    resolved: Option<String>,
+    line: usize,
+    column: usize,
+}
+
+/// A scope opened by `.proc` or `.scope`.
+struct Scope {
+    /// Directive that opened the scope.
+    directive: String,
+    name: String,
+    line: usize,
+    /// Scope of the local labels before the scope was opened, given back when it is closed.
+    locals: String,
+}
+
+/// State of an `.if` block.
+struct Condition {
+    /// Whether the lines of the current branch are assembled.
+    active: bool,
+    /// Whether a branch was taken already, or the whole block is skipped.
+    done: bool,
+    /// Whether `.else` was seen, after which no other branch may follow.
+    otherwise: bool,
+    /// Line of the `.if`.
+    line: usize,
+}
+
+impl Assembler {
+    fn new(memory_start: u32, cpu: CpuOptions, lints: LintLevels) -> Self {
+        let current_addr = memory_start;
+        Assembler {
+            items: vec![],
+            symbols: SymbolTable::new(),
+            fixups: vec![],
+            current_addr,
+            regions: vec![(current_addr, current_addr, 0)],
+            positions: vec![],
+            references: vec![],
+            cpu,
+            reporter: Reporter::new(lints),
+            macros: HashMap::new(),
+            definition: None,
+            repetition: None,
+            expansions: 0,
+            trace: vec![],
+            conditions: vec![],
+            probes: vec![],
+            sources: Sources::new(),
+            include_paths: vec![],
+            labels: LocalLabels::new(),
+            scopes: vec![],
+            charset: Charset::new(),
+        }
+    }
+    /// Assemble a line, or add it to the macro being defined.
+    fn line(&mut self, line: &Line) -> Vec<CompileError> {
+        if let Some((definition, depth)) = self.definition.as_mut() {
+            match macros::block_directive(line) {
+                Some(".macro") => *depth += 1,
+                Some(".endmacro") | Some(".endm") if *depth == 0 => {
+                    let (definition, _) = self.definition.take().unwrap();
+                    self.macros.insert(definition.name.clone(), definition);
+                    return vec![];
+                }
+                Some(".endmacro") | Some(".endm") => *depth -= 1,
+                _ => {}
+            }
+            definition.body.push(line.clone());
+            return vec![];
+        }
+        if let Some((repetition, depth)) = self.repetition.as_mut() {
+            match macros::block_directive(line) {
+                Some(".rept") | Some(".for") => *depth += 1,
+                Some(".endr") | Some(".endfor") if *depth == 0 => {
+                    let (repetition, _) = self.repetition.take().unwrap();
+                    return self.repeat(repetition);
+                }
+                Some(".endr") | Some(".endfor") => *depth -= 1,
+                _ => {}
+            }
+            repetition.body.push(line.clone());
+            return vec![];
+        }
+        if let Some(errors) = self.conditional(line) {
+            return errors;
+        }
+        let tokens = self.labels.define(&line.tokens);
+        let mut statement = match &tokens[..] {
+            // Macros are invoked like instructions, which may start at the first column.
+            [name, operand @ ..]
+                if name.kind == TokenKind::Identifier && self.macros.contains_key(&name.text) =>
+            {
+                Statement {
+                    line: line.number,
+                    address: InstructionAddress::None,
+                    mnemonic: Some(name.clone()),
+                    operand: operand.to_vec(),
+                }
+            }
+            _ => match Statement::parse(&Line {
+                number: line.number,
+                tokens,
+                tabbed: line.tabbed,
+            }) {
+                Ok(statement) => statement,
+                Err(err) => return vec![err],
+            },
+        };
+        if let ([first, second, ..], InstructionAddress::Label(name)) =
+            (&line.tokens[..], &statement.address)
+        {
+            // Only a tab after `C000` makes it an address, as in the disassembler's listings.
+            if first.column == 1
+                && !second.is_symbol(":")
+                && *name == first.text
+                && parser::is_address(first, true)
+            {
+                let message = format!(
+                    "`{}` is a label here, put a tab after it for an address or a `:` for a label",
+                    name
+                );
+                if let Err(err) =
+                    self.reporter
+                        .report(Lint::HexLabel, line.number, Some(first.column), message)
+                {
+                    return vec![err];
+                }
+            }
+        }
+        self.labels.localize(&mut statement);
+        let mnemonic = match &statement.mnemonic {
+            Some(mnemonic) => mnemonic,
+            None => return self.statement(&statement).err().into_iter().collect(),
+        };
+        match mnemonic.text.as_str() {
+            ".macro" => match Macro::from_statement(&statement) {
+                Ok(definition) => {
+                    self.definition = Some((definition, 0));
+                    vec![]
+                }
+                Err(err) => vec![err],
+            },
+            ".endmacro" | ".endm" => vec![CompileError::at(
+                ErrorKind::SyntaxError,
+                statement.line,
+                mnemonic.column,
+                format!("`{}` without `.macro`", mnemonic.text),
+            )],
+            ".include" => self.include(&statement),
+            ".rept" | ".for" => self.open_repetition(&statement),
+            ".endr" | ".endfor" => vec![CompileError::at(
+                ErrorKind::SyntaxError,
+                statement.line,
+                mnemonic.column,
+                format!("`{}` without `.rept` or `.for`", mnemonic.text),
+            )],
+            name if self.macros.contains_key(name) => self.expand(&statement),
+            _ => self.statement(&statement).err().into_iter().collect(),
+        }
+    }
+    /// Follow the conditional directives, returning `None` for lines to assemble.
+    fn conditional(&mut self, line: &Line) -> Option<Vec<CompileError>> {
+        let directive = macros::block_directive(line)
+            .unwrap_or_default()
+            .to_string();
+        let active = self
+            .conditions
+            .last()
+            .is_none_or(|condition| condition.active);
+        let condition = match directive.as_str() {
+            ".if" | ".ifdef" | ".ifndef" => {
+                // A skipped block is still nested, so that its `.endif` ends it.
+                let (value, errors) = if active {
+                    match self.condition(line) {
+                        Ok(value) => (value, vec![]),
+                        Err(err) => (false, vec![err]),
+                    }
+                } else {
+                    (false, vec![])
+                };
+                self.conditions.push(Condition {
+                    active: value,
+                    done: value || !active,
+                    otherwise: false,
+                    line: line.number,
+                });
+                return Some(errors);
+            }
+            ".elseif" | ".else" | ".endif" => match self.conditions.last() {
+                Some(condition) => condition,
+                None => {
+                    return Some(vec![CompileError::at(
+                        ErrorKind::SyntaxError,
+                        line.number,
+                        line.tokens[0].column,
+                        format!("`{}` without `.if`", directive),
+                    )])
+                }
+            },
+            _ if active => return None,
+            _ => return Some(vec![]),
+        };
+        if condition.otherwise && directive != ".endif" {
+            return Some(vec![CompileError::at(
+                ErrorKind::SyntaxError,
+                line.number,
+                line.tokens[0].column,
+                format!("`{}` after `.else`", directive),
+            )]);
+        }
+        let done = condition.done;
+        let mut errors = vec![];
+        match directive.as_str() {
+            ".elseif" => {
+                let value = !done
+                    && match self.condition(line) {
+                        Ok(value) => value,
+                        Err(err) => {
+                            errors.push(err);
+                            false
                         }
+                    };
+                let condition = self.conditions.last_mut().unwrap();
+                condition.active = value;
+                condition.done = done || value;
+            }
+            ".else" => {
+                let condition = self.conditions.last_mut().unwrap();
+                condition.active = !done;
+                condition.done = true;
+                condition.otherwise = true;
+            }
+            _ => {
+                self.conditions.pop();
+            }
+        }
+        Some(errors)
+    }
+    /// Evaluate the condition of an `.if`, `.elseif`, `.ifdef` or `.ifndef` line.
+    fn condition(&mut self, line: &Line) -> Result<bool> {
+        let mut statement = Statement::parse(line)?;
+        self.charset.encode_characters(&mut statement)?;
+        let directive = statement
+            .mnemonic
+            .as_ref()
+            .expect("conditional directives have a mnemonic");
+        match directive.text.as_str() {
+            ".ifdef" | ".ifndef" => {
+                let name = match &statement.operand[..] {
+                    [name] if name.kind == TokenKind::Identifier => name,
+                    _ => {
+                        return Err(CompileError::at(
+                            ErrorKind::SyntaxError,
+                            statement.line,
+                            directive.column,
+                            "expected a symbol name",
+                        ))
+                    }
+                };
+                let defined = self.symbols.get(&name.text).is_some();
+                if !defined {
+                    // Defining the symbol further down would change the answer.
+                    self.probes.push((
+                        directive.text.clone(),
+                        name.text.clone(),
+                        self.symbols.scope.clone(),
+                        statement.line,
+                        name.column,
+                    ));
+                }
+                Ok(defined == (directive.text == ".ifdef"))
+            }
+            _ => {
+                let value = data::known_value(
+                    &self.symbols,
+                    &statement.operand,
+                    &statement,
+                    self.pc(&statement)?,
+                )?;
+                Ok(value != 0)
+            }
+        }
+    }
+    /// Expand the macro invoked by `statement`, whose errors point to the invocation in a note.
+    fn expand(&mut self, statement: &Statement) -> Vec<CompileError> {
+        let mnemonic = statement
+            .mnemonic
+            .as_ref()
+            .expect("macro invocations have a name");
+        let definition = self.macros[&mnemonic.text].clone();
+        if self.trace.len() >= macros::EXPANSION_LIMIT {
+            return vec![CompileError::at(
+                ErrorKind::SyntaxError,
+                statement.line,
+                mnemonic.column,
+                format!(
+                    "macro expansions nested deeper than {} levels, while expanding `{}`",
+                    macros::EXPANSION_LIMIT,
+                    definition.name
+                ),
+            )];
+        }
+        // A label in front of the invocation labels the first line of the expansion.
+        let label = Statement {
+            mnemonic: None,
+            operand: vec![],
+            ..statement.clone()
+        };
+        if let Err(err) = self.statement(&label) {
+            return vec![err];
+        }
+        let arguments = macros::arguments(&statement.operand, definition.params.len());
+        if arguments.len() != definition.params.len() {
+            let err = CompileError::at(
+                ErrorKind::SyntaxError,
+                statement.line,
+                mnemonic.column,
+                format!(
+                    "macro `{}` takes {} argument(s) but {} were given",
+                    definition.name,
+                    definition.params.len(),
+                    arguments.len()
+                ),
+            );
+            return vec![err.with_note(Note::new(definition.line, "macro defined here"))];
+        }
+        self.expansions += 1;
+        let note = Note {
+            column: Some(mnemonic.column),
+            ..Note::new(
+                statement.line,
+                format!("in this expansion of macro `{}`", definition.name),
+            )
+        };
+        self.trace.push(note.clone());
+        let mut errors = vec![];
+        for line in definition.expand(&arguments, self.expansions) {
+            errors.append(&mut self.line(&line));
+        }
+        self.trace.pop();
+        for err in errors.iter_mut() {
+            err.notes.push(note.clone());
+        }
+        errors
+    }
+    /// Start recording the body of a `.rept` or `.for` statement.
+    fn open_repetition(&mut self, statement: &Statement) -> Vec<CompileError> {
+        // A label in front of the block labels its first line.
+        let label = Statement {
+            mnemonic: None,
+            operand: vec![],
+            ..statement.clone()
+        };
+        let mut errors: Vec<CompileError> = self.statement(&label).err().into_iter().collect();
+        // The body is recorded even if the header is wrong, so that its `.endr` still matches.
+        let repeat = self.repeat_header(statement).unwrap_or_else(|err| {
+            errors.push(err);
+            Repeat::Count(0)
+        });
+        self.repetition = Some((
+            Repetition {
+                statement: statement.clone(),
+                repeat,
+                body: vec![],
+            },
+            0,
+        ));
+        errors
+    }
+    /// Read how many times a `.rept` or `.for` statement repeats its body.
+    fn repeat_header(&self, statement: &Statement) -> Result<Repeat> {
+        let directive = statement
+            .mnemonic
+            .as_ref()
+            .expect("directive statements have a mnemonic");
+        let pc = self.pc(statement)?;
+        if directive.text == ".rept" {
+            let count = data::known_value(&self.symbols, &statement.operand, statement, pc)?;
+            if !(0..=macros::REPETITION_LIMIT).contains(&count) {
+                return Err(CompileError::at(
+                    ErrorKind::ValueOutOfRange,
+                    statement.line,
+                    statement.operand[0].column,
+                    "value out of range",
+                ));
+            }
+            return Ok(Repeat::Count(count));
+        }
+        let error = || {
+            CompileError::at(
+                ErrorKind::SyntaxError,
+                statement.line,
+                directive.column,
+                "expected `.for variable = start, condition, step`",
+            )
+        };
+        let (variable, start, condition, step) = match &data::split_arguments(&statement.operand)[..]
+        {
+            [[variable, equals, start @ ..], condition, step]
+                if variable.kind == TokenKind::Identifier && equals.is_symbol("=") =>
+            {
+                (variable, start, *condition, *step)
+            }
+            _ => return Err(error()),
+        };
+        let next = macros::next_value(step, &variable.text).ok_or_else(|| {
+            CompileError::at(
+                ErrorKind::SyntaxError,
+                statement.line,
+                step.first().unwrap_or(directive).column,
+                format!(
+                    "expected a step of `{0}`, such as `{0}++`, `{0} += 2` or `{0} = {0} * 2`",
+                    variable.text
+                ),
+            )
+        })?;
+        if condition.is_empty() {
+            return Err(error());
+        }
+        Ok(Repeat::For {
+            variable: variable.text.clone(),
+            start: data::known_value(&self.symbols, start, statement, pc)?,
+            condition: condition.to_vec(),
+            next,
+        })
+    }
+    /// Evaluate `tokens` from the header of a `.for` with its variable set to `value`.
+    fn loop_value(
+        &self,
+        tokens: &[Token],
+        variable: &str,
+        value: i64,
+        statement: &Statement,
+    ) -> Result<i64> {
+        let tokens: Vec<Token> = tokens
+            .iter()
+            .flat_map(|token| {
+                if token.kind == TokenKind::Identifier && token.text == variable {
+                    macros::literal(value, token.column)
+                } else {
+                    vec![token.clone()]
+                }
+            })
+            .collect();
+        data::known_value(&self.symbols, &tokens, statement, self.pc(statement)?)
+    }
+    /// Assemble the body of a `.rept` or `.for` block once per iteration, with the labels it
+    /// defines renamed in each.
+    fn repeat(&mut self, repetition: Repetition) -> Vec<CompileError> {
+        let statement = &repetition.statement;
+        let directive = statement
+            .mnemonic
+            .as_ref()
+            .expect("directive statements have a mnemonic");
+        if self.trace.len() >= macros::EXPANSION_LIMIT {
+            return vec![CompileError::at(
+                ErrorKind::SyntaxError,
+                statement.line,
+                directive.column,
+                format!(
+                    "macro expansions and repetitions nested deeper than {} levels",
+                    macros::EXPANSION_LIMIT
+                ),
+            )];
+        }
+        let (variable, mut value) = match &repetition.repeat {
+            Repeat::Count(_) => (None, 0),
+            Repeat::For {
+                variable, start, ..
+            } => (Some(variable.clone()), *start),
+        };
+        // The body is expanded like a macro taking the loop variable as its parameter.
+        let body = Macro {
+            name: directive.text.clone(),
+            params: variable.iter().cloned().collect(),
+            body: repetition.body.clone(),
+            line: statement.line,
+        };
+        let mut errors = vec![];
+        for iteration in 0.. {
+            let message = match &repetition.repeat {
+                Repeat::Count(count) if iteration == *count => break,
+                Repeat::Count(_) => format!("in iteration {} of this `.rept`", iteration + 1),
+                Repeat::For {
+                    variable,
+                    condition,
+                    ..
+                } => match self.loop_value(condition, variable, value, statement) {
+                    Ok(0) => break,
+                    Ok(_) => format!(
+                        "in the iteration of this `.for` where `{}` is {}",
+                        variable, value
+                    ),
+                    Err(err) => {
+                        errors.push(err);
+                        break;
+                    }
+                },
+            };
+            if iteration == macros::REPETITION_LIMIT {
+                errors.push(CompileError::at(
+                    ErrorKind::ValueOutOfRange,
+                    statement.line,
+                    directive.column,
+                    format!(
+                        "`.for` still running after {} iterations",
+                        macros::REPETITION_LIMIT
+                    ),
+                ));
+                break;
+            }
+            self.expansions += 1;
+            let note = Note {
+                column: Some(directive.column),
+                ..Note::new(statement.line, message)
+            };
+            let literal = macros::literal(value, directive.column);
+            let arguments: Vec<&[Token]> = variable.iter().map(|_| &literal[..]).collect();
+            self.trace.push(note.clone());
+            let mut iteration_errors = vec![];
+            for line in body.expand(&arguments, self.expansions) {
+                iteration_errors.append(&mut self.line(&line));
+            }
+            self.trace.pop();
+            for err in iteration_errors.iter_mut() {
+                err.notes.push(note.clone());
+            }
+            // Further iterations would most likely repeat the same errors.
+            if !iteration_errors.is_empty() {
+                errors.append(&mut iteration_errors);
+                break;
+            }
+            if let Repeat::For { variable, next, .. } = &repetition.repeat {
+                match self.loop_value(next, variable, value, statement) {
+                    Ok(next) => value = next,
+                    Err(err) => {
+                        errors.push(err);
+                        break;
                     }
-                    current_addr += instruction.operands.len() as u16 + 1;
-                    result.push(instruction);
                 }
-                Err(err) => return Err(CompileError::new(line_number, err)),
             }
-            line_number += 1;
         }
-        self.instructions = result.clone();
-        Ok(result)
+        errors
+    }
+    /// Find the file named by the string `argument` of `.include` or `.incbin`.
+    fn find_file(&self, argument: &[Token], statement: &Statement) -> Result<PathBuf> {
+        let directive = statement
+            .mnemonic
+            .as_ref()
+            .expect("directive statements have a mnemonic");
+        let name = match argument {
+            [name] if name.kind == TokenKind::String => name,
+            _ => {
+                return Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    statement.line,
+                    argument.first().unwrap_or(directive).column,
+                    "expected a file name in quotes",
+                ))
+            }
+        };
+        self.sources
+            .resolve(&name.text, statement.line, &self.include_paths)
+            .ok_or_else(|| {
+                CompileError::at(
+                    ErrorKind::Io,
+                    statement.line,
+                    name.column,
+                    format!(
+                        "can't find `{}` next to this file or in the include paths",
+                        name.text
+                    ),
+                )
+            })
+    }
+    /// Assemble the file named by an `.include` statement, in place of the statement.
+    fn include(&mut self, statement: &Statement) -> Vec<CompileError> {
+        if let InstructionAddress::Label(label) = &statement.address {
+            if let Err(err) = self
+                .pc(statement)
+                .and_then(|pc| self.define_label(label, pc, statement))
+            {
+                return vec![err];
+            }
+        }
+        let path = match self.find_file(&statement.operand, statement) {
+            Ok(path) => path,
+            Err(err) => return vec![err],
+        };
+        let column = statement.operand[0].column;
+        if self.sources.is_including(&path, statement.line) {
+            return vec![CompileError::at(
+                ErrorKind::IncludeCycle,
+                statement.line,
+                column,
+                format!("`{}` includes itself", path.display()),
+            )];
+        }
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text.replace("\r\n", "\n"),
+            Err(err) => {
+                return vec![CompileError::at(
+                    ErrorKind::Io,
+                    statement.line,
+                    column,
+                    format!("can't read `{}`: {}", path.display(), err),
+                )]
+            }
+        };
+        let name = path.display().to_string();
+        let (lines, mut errors) =
+            self.sources
+                .add(Some(name), Some(path), text, Some(statement.line));
+        for line in &lines {
+            errors.append(&mut self.line(line));
+        }
+        errors
+    }
+    /// Read the bytes of an `.incbin` statement: a file name, then an optional offset and
+    /// length within the file.
+    fn incbin(&self, statement: &Statement) -> Result<Data> {
+        let arguments = data::split_arguments(&statement.operand);
+        let path = self.find_file(arguments.first().copied().unwrap_or(&[]), statement)?;
+        let column = statement.operand[0].column;
+        let bytes = std::fs::read(&path).map_err(|err| {
+            CompileError::at(
+                ErrorKind::Io,
+                statement.line,
+                column,
+                format!("can't read `{}`: {}", path.display(), err),
+            )
+        })?;
+        let pc = self.pc(statement)?;
+        let mut bounds = vec![];
+        for argument in arguments.iter().skip(1) {
+            bounds.push(data::known_value(&self.symbols, argument, statement, pc)?);
+        }
+        let (start, length) = match bounds[..] {
+            [] => (0, bytes.len() as i64),
+            [start] => (start, bytes.len() as i64 - start),
+            [start, length] => (start, length),
+            _ => {
+                return Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    statement.line,
+                    column,
+                    "expected a file name, an optional offset and an optional length",
+                ))
+            }
+        };
+        if start < 0 || length < 0 || start + length > bytes.len() as i64 {
+            return Err(CompileError::at(
+                ErrorKind::ValueOutOfRange,
+                statement.line,
+                arguments[1][0].column,
+                format!(
+                    "offset and length out of range of `{}`, which has {} bytes",
+                    path.display(),
+                    bytes.len()
+                ),
+            ));
+        }
+        Ok(Data {
+            directive: ".incbin".to_string(),
+            bytes: bytes[start as usize..(start + length) as usize].to_vec(),
+            address: statement.address.clone(),
+        })
+    }
+    /// Check that the program counter is still addressable, for an item or label at `statement`.
+    fn pc(&self, statement: &Statement) -> Result<u32> {
+        if self.current_addr >= self.cpu.model.address_space() {
+            Err(CompileError::new(
+                ErrorKind::AddressOutOfRange,
+                statement.line,
+                "address out of range",
+            ))
+        } else {
+            Ok(self.current_addr)
+        }
+    }
+    /// Define a symbol, which must not be defined already unless it is a `.set` variable.
+    fn define(
+        &mut self,
+        name: &str,
+        value: i64,
+        kind: SymbolKind,
+        statement: &Statement,
+    ) -> Result<()> {
+        let first = self.symbols.local(name).map(|symbol| symbol.line);
+        self.symbols
+            .define(name, value, kind, statement.line)
+            .map_err(|_| {
+                let err = CompileError::new(
+                    ErrorKind::DuplicateLabel(name.to_string()),
+                    statement.line,
+                    format!("duplicate definition of `{}`", name),
+                );
+                match first {
+                    Some(0) => CompileError {
+                        message: format!("`{}` is a predefined symbol", name),
+                        ..err
+                    },
+                    Some(line) => err.with_note(Note::new(line, "first defined here")),
+                    None => err,
+                }
+            })
+    }
+    /// Define a label at `addr`.
+    fn define_label(&mut self, label: &str, addr: u32, statement: &Statement) -> Result<()> {
+        self.define(label, addr as i64, SymbolKind::Label, statement)?;
+        if OPCODES_MAP.contains_key(label.to_uppercase().as_str()) {
+            self.reporter.report(
+                Lint::LabelShadowsMnemonic,
+                statement.line,
+                None,
+                format!("label `{}` has the name of an instruction", label),
+            )?;
+        }
+        Ok(())
+    }
+    /// Select the encoding named by an `.encoding` statement.
+    fn set_encoding(&mut self, statement: &Statement, directive: &Token) -> Result<()> {
+        self.charset.encoding = match &statement.operand[..] {
+            [name] if name.kind == TokenKind::Identifier => Encoding::from_name(&name.text)
+                .ok_or_else(|| {
+                    let names: Vec<&str> = Encoding::ALL.iter().map(|e| e.name()).collect();
+                    CompileError::at(
+                        ErrorKind::SyntaxError,
+                        statement.line,
+                        name.column,
+                        format!(
+                            "unknown encoding `{}`, expected one of {}",
+                            name.text,
+                            names.join(", ")
+                        ),
+                    )
+                })?,
+            _ => {
+                return Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    statement.line,
+                    directive.column,
+                    "expected an encoding name",
+                ))
+            }
+        };
+        Ok(())
+    }
+    /// Change the code of a character, or of a range of characters, in the current encoding
+    /// with `.charmap 'c', code` or `.charmap 'first', 'last', code`.
+    fn charmap(&mut self, statement: &Statement, directive: &Token) -> Result<()> {
+        let arguments = data::split_arguments(&statement.operand);
+        let character = |argument: &[Token]| match argument {
+            [c] if c.kind == TokenKind::Character => c.text.chars().next(),
+            _ => None,
+        };
+        let range = match &arguments[..] {
+            [first, code] => character(first).map(|first| (first, first, code)),
+            [first, last, code] => character(first)
+                .zip(character(last))
+                .map(|(first, last)| (first, last, code)),
+            _ => None,
+        };
+        let (first, last, code) = range.ok_or_else(|| {
+            CompileError::at(
+                ErrorKind::SyntaxError,
+                statement.line,
+                directive.column,
+                "expected a character or a range of characters, and a code",
+            )
+        })?;
+        let value = data::known_value(&self.symbols, code, statement, self.pc(statement)?)?;
+        let codes = value..value + (first..=last).count() as i64;
+        if codes.is_empty() || codes.start < 0 || codes.end > 0x100 {
+            return Err(CompileError::at(
+                ErrorKind::ValueOutOfRange,
+                statement.line,
+                code[0].column,
+                "codes out of range of a byte",
+            ));
+        }
+        for (c, code) in (first..=last).zip(codes) {
+            self.charset.map(c, code as u8);
+        }
+        Ok(())
+    }
+    /// Open the scope named by a `.proc` or `.scope` statement. A `.proc` is a label too.
+    fn open_scope(&mut self, statement: &Statement, directive: &Token) -> Result<()> {
+        let name = match &statement.operand[..] {
+            [name]
+                if name.kind == TokenKind::Identifier
+                    && !name.text.contains("::")
+                    && !labels::is_local(&name.text) =>
+            {
+                name.text.clone()
+            }
+            _ => {
+                return Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    statement.line,
+                    directive.column,
+                    "expected a scope name",
+                ))
+            }
+        };
+        // The scope is opened even if its label is wrong, so that its end still matches.
+        let label = if directive.text == ".proc" {
+            self.pc(statement)
+                .and_then(|pc| self.define_label(&name, pc, statement))
+        } else {
+            Ok(())
+        };
+        self.scopes.push(Scope {
+            directive: directive.text.clone(),
+            name: name.clone(),
+            line: statement.line,
+            locals: std::mem::take(&mut self.labels.scope),
+        });
+        self.symbols.scope = symbols::qualify(&self.symbols.scope, &name);
+        label
+    }
+    /// Close the innermost scope, which `directive` must match.
+    fn close_scope(&mut self, statement: &Statement, directive: &Token) -> Result<()> {
+        let opening = format!(".{}", &directive.text[".end".len()..]);
+        match self.scopes.last() {
+            Some(scope) if scope.directive == opening => {}
+            Some(scope) => {
+                let err = CompileError::at(
+                    ErrorKind::SyntaxError,
+                    statement.line,
+                    directive.column,
+                    format!(
+                        "`{}` closing `{} {}`",
+                        directive.text, scope.directive, scope.name
+                    ),
+                );
+                return Err(err.with_note(Note::new(scope.line, "opened here")));
+            }
+            None => {
+                return Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    statement.line,
+                    directive.column,
+                    format!("`{}` without `{}`", directive.text, opening),
+                ))
+            }
+        }
+        let scope = self.scopes.pop().unwrap();
+        self.labels.scope = scope.locals;
+        self.symbols.scope = symbols::split_scope(&self.symbols.scope).0.to_string();
+        Ok(())
+    }
+    /// Assemble a single statement.
+    fn statement(&mut self, statement: &Statement) -> Result<()> {
+        // Character literals are codes in the encoding in use, except in `.charmap` which maps them.
+        let encoded;
+        let statement = if statement
+            .operand
+            .iter()
+            .any(|token| token.kind == TokenKind::Character)
+            && statement
+                .mnemonic
+                .as_ref()
+                .is_none_or(|mnemonic| mnemonic.text != ".charmap")
+        {
+            let mut copy = statement.clone();
+            self.charset.encode_characters(&mut copy)?;
+            encoded = copy;
+            &encoded
+        } else {
+            statement
+        };
+        for token in &statement.operand {
+            if token.kind == TokenKind::Identifier {
+                self.references.push(Reference {
+                    name: token.text.clone(),
+                    scope: self.symbols.scope.clone(),
+                    resolved: self.symbols.resolve(&token.text),
+                    line: statement.line,
+                    column: token.column,
+                });
+            }
+        }
+        let mnemonic = match &statement.mnemonic {
+            Some(mnemonic) => mnemonic,
+            None => {
+                // Label on a line of its own.
+                if let InstructionAddress::Label(label) = &statement.address {
+                    self.define_label(label, self.pc(statement)?, statement)?;
+                }
+                return Ok(());
+            }
+        };
+        if mnemonic.text == ".equ" || mnemonic.text == ".set" {
+            let name = match &statement.address {
+                InstructionAddress::Label(name) => name,
+                _ => {
+                    return Err(CompileError::at(
+                        ErrorKind::SyntaxError,
+                        statement.line,
+                        mnemonic.column,
+                        "missing symbol name",
+                    ))
+                }
+            };
+            let value = data::known_value(
+                &self.symbols,
+                &statement.operand,
+                statement,
+                self.pc(statement)?,
+            )?;
+            let kind = if mnemonic.text == ".set" {
+                SymbolKind::Variable
+            } else {
+                SymbolKind::Constant
+            };
+            return self.define(name, value, kind, statement);
+        }
+        if mnemonic.text == ".org" {
+            let origin = data::known_value(
+                &self.symbols,
+                &statement.operand,
+                statement,
+                self.pc(statement)?,
+            )?;
+            if !(0..self.cpu.model.address_space() as i64).contains(&origin) {
+                return Err(CompileError::at(
+                    ErrorKind::ValueOutOfRange,
+                    statement.line,
+                    statement.operand[0].column,
+                    "value out of range",
+                ));
+            }
+            self.current_addr = origin as u32;
+            self.regions
+                .push((self.current_addr, self.current_addr, statement.line));
+            if let InstructionAddress::Label(label) = &statement.address {
+                self.define_label(label, origin as u32, statement)?;
+            }
+            return Ok(());
+        }
+        if let InstructionAddress::Address(origin) = statement.address {
+            // An address in front of the line moves the program counter like `.org`.
+            self.current_addr = origin;
+            self.regions
+                .push((self.current_addr, self.current_addr, statement.line));
+        }
+        let pc = self.pc(statement)?;
+        // Define the label first, so that it is known even if the rest of the line is wrong.
+        if let InstructionAddress::Label(label) = &statement.address {
+            self.define_label(label, pc, statement)?;
+        }
+        match mnemonic.text.as_str() {
+            ".encoding" => return self.set_encoding(statement, mnemonic),
+            ".charmap" => return self.charmap(statement, mnemonic),
+            ".proc" | ".scope" => return self.open_scope(statement, mnemonic),
+            ".endproc" | ".endscope" => return self.close_scope(statement, mnemonic),
+            _ => {}
+        }
+        if let Some(width) = register_width(&mnemonic.text) {
+            if self.cpu.model != CpuModel::Wdc65816 {
+                return Err(CompileError::at(
+                    ErrorKind::UnknownDirective,
+                    statement.line,
+                    mnemonic.column,
+                    format!("`{}` is only available on the 65816", mnemonic.text),
+                ));
+            }
+            match width {
+                RegisterWidth::Accumulator(wide) => self.cpu.wide_accumulator = wide,
+                RegisterWidth::Index(wide) => self.cpu.wide_index = wide,
+            }
+            return Ok(());
+        }
+        let (mut item, mut item_fixups) = if mnemonic.text == ".incbin" {
+            (Item::Data(self.incbin(statement)?), vec![])
+        } else if mnemonic.text.starts_with('.') {
+            let (data, fixups) = Data::from_statement(&self.symbols, statement, pc, &self.charset)?;
+            (Item::Data(data), fixups)
+        } else {
+            if mnemonic.text.starts_with('*') {
+                self.reporter.report(
+                    Lint::UndocumentedOpcode,
+                    statement.line,
+                    Some(mnemonic.column),
+                    format!("undocumented opcode `{}`", mnemonic.text),
+                )?;
+            }
+            if mnemonic.text == "SED" && self.cpu.model == CpuModel::Ricoh2A03 {
+                self.reporter.report(
+                    Lint::DecimalMode,
+                    statement.line,
+                    Some(mnemonic.column),
+                    "`SED` has no effect, the 2A03 has no decimal mode",
+                )?;
+            }
+            let (instruction, fixups) = Instruction::from_statement(
+                &self.symbols,
+                statement,
+                pc,
+                &self.cpu,
+                &mut self.reporter,
+            )?;
+            (Item::Instruction(instruction), fixups)
+        };
+        *item.address_mut() = InstructionAddress::Address(pc);
+        for fixup in item_fixups.iter_mut() {
+            fixup.index = self.items.len();
+            fixup.notes = self.trace.iter().rev().cloned().collect();
+            fixup.scope = self.symbols.scope.clone();
+        }
+        self.fixups.append(&mut item_fixups);
+        self.current_addr += item.size() as u32;
+        if self.current_addr > self.cpu.model.address_space() {
+            return Err(CompileError::new(
+                ErrorKind::AddressOutOfRange,
+                statement.line,
+                "address out of range",
+            ));
+        }
+        self.regions.last_mut().unwrap().1 = self.current_addr;
+        let column = statement.operand.first().unwrap_or(mnemonic).column;
+        self.positions.push((statement.line, column));
+        self.items.push(item);
+        Ok(())
+    }
+    /// Check the memory layout and patch the forward references, once every label is known.
+    fn finish(&mut self) -> Vec<CompileError> {
+        let mut errors = vec![];
+        if let Some((definition, _)) = self.definition.take() {
+            errors.push(CompileError::new(
+                ErrorKind::SyntaxError,
+                definition.line,
+                format!("macro `{}` is missing its `.endmacro`", definition.name),
+            ));
+        }
+        if let Some((repetition, _)) = self.repetition.take() {
+            let statement = repetition.statement;
+            let directive = statement.mnemonic.as_ref().unwrap();
+            errors.push(CompileError::at(
+                ErrorKind::SyntaxError,
+                statement.line,
+                directive.column,
+                format!("`{}` is missing its `.endr`", directive.text),
+            ));
+        }
+        for scope in std::mem::take(&mut self.scopes) {
+            errors.push(CompileError::new(
+                ErrorKind::SyntaxError,
+                scope.line,
+                format!(
+                    "`{} {}` is missing its `.end{}`",
+                    scope.directive,
+                    scope.name,
+                    &scope.directive[1..]
+                ),
+            ));
+        }
+        for condition in std::mem::take(&mut self.conditions) {
+            errors.push(CompileError::new(
+                ErrorKind::SyntaxError,
+                condition.line,
+                "conditional block is missing its `.endif`",
+            ));
+        }
+        for (directive, name, scope, line, column) in std::mem::take(&mut self.probes) {
+            self.symbols.scope = scope;
+            if let Some(symbol) = self.symbols.get(&name) {
+                let err = CompileError::at(
+                    ErrorKind::ForwardReference(name.clone()),
+                    line,
+                    column,
+                    format!(
+                        "`{}` tests `{}` before it is defined, so it is always taken as undefined",
+                        directive, name
+                    ),
+                );
+                errors.push(err.with_note(Note::new(symbol.line, "defined here")));
+            }
+        }
+        self.regions.retain(|(start, end, _)| start < end);
+        self.regions.sort_by_key(|(start, _, _)| *start);
+        for pair in self.regions.windows(2) {
+            let ((_, end, first_line), (start, _, second_line)) = (pair[0], pair[1]);
+            if start < end {
+                errors.push(CompileError::new(
+                    ErrorKind::OverlappingRegions,
+                    first_line.max(second_line),
+                    "overlapping memory regions",
+                ));
+            }
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            if let Err(mut err) = self.patch(&fixup) {
+                err.notes.extend(fixup.notes);
+                errors.push(err);
+            }
+        }
+        self.symbols.scope = String::new();
+        for reference in &self.references {
+            // A name is looked up once its symbol is known, which a symbol of the same name
+            // defined later in an inner scope would have changed.
+            let resolved = self.symbols.resolve_from(&reference.scope, &reference.name);
+            if let (Some(early), Some(late)) = (&reference.resolved, &resolved) {
+                if early != late {
+                    let err = CompileError::at(
+                        ErrorKind::ForwardReference(reference.name.clone()),
+                        reference.line,
+                        reference.column,
+                        format!(
+                            "`{}` refers to `{}` here, but to `{}` once it is defined",
+                            reference.name, early, late
+                        ),
+                    );
+                    let line = self.symbols.get(late).map_or(0, |symbol| symbol.line);
+                    errors.push(err.with_note(Note::new(line, "defined here")));
+                }
+            }
+        }
+        errors.append(&mut self.lint());
+        errors
+    }
+    /// Check the lints that need the final output and every symbol, returning the denied ones.
+    fn lint(&mut self) -> Vec<CompileError> {
+        let mut reports = vec![];
+        for (item, (line, column)) in self.items.iter().zip(&self.positions) {
+            let instruction = match item {
+                Item::Instruction(instruction) => instruction,
+                Item::Data(_) => continue,
+            };
+            let origin = match instruction.address {
+                InstructionAddress::Address(origin) => origin,
+                _ => unreachable!("compiled items always have an address"),
+            };
+            // The 65C02 fixed the page wrap of indirect jumps.
+            if self.cpu.model.is_nmos()
+                && instruction.mnemonic == "JMP"
+                && instruction.mode == AddressingMode::Indirect
+                && instruction.operands[0] == 0xff
+            {
+                let pointer =
+                    u16::from_le_bytes([instruction.operands[0], instruction.operands[1]]);
+                let message = format!(
+                    "`JMP (${:04X})` reads the high byte of its target from ${:04X}",
+                    pointer,
+                    pointer & 0xff00
+                );
+                reports.push((Lint::JmpIndirectPageWrap, *line, Some(*column), message));
+            }
+            // Long branches take no extra cycle.
+            let target = match instruction.mode {
+                AddressingMode::RelativeLong => None,
+                _ => instruction.branch_target(),
+            };
+            if let Some(target) = target {
+                let next = origin + instruction.operands.len() as u32 + 1;
+                if (next ^ target) & 0xffff00 != 0 {
+                    let message = format!(
+                        "branch to ${} crosses a page boundary",
+                        InstructionAddress::Address(target)
+                    );
+                    reports.push((Lint::BranchPageCrossing, *line, Some(*column), message));
+                }
+            }
+        }
+        let used: HashSet<String> = self
+            .references
+            .iter()
+            .filter_map(|reference| self.symbols.resolve_from(&reference.scope, &reference.name))
+            .collect();
+        for (name, symbol) in self.symbols.iter() {
+            // Numbered and anonymous labels are throwaway names, which need no use.
+            if symbol.kind == SymbolKind::Label
+                && !used.contains(name)
+                && !labels::is_numbered(symbols::split_scope(name).1)
+            {
+                let message = format!("label `{}` is never used", name);
+                reports.push((Lint::UnusedLabel, symbol.line, None, message));
+            }
+        }
+        reports
+            .into_iter()
+            .filter_map(|(lint, line, column, message)| {
+                self.reporter.report(lint, line, column, message).err()
+            })
+            .collect()
+    }
+    fn patch(&mut self, fixup: &Fixup) -> Result<()> {
+        self.symbols.scope = fixup.scope.clone();
+        let symbols = &self.symbols;
+        let item = &mut self.items[fixup.index];
+        let origin = match item.address() {
+            InstructionAddress::Address(origin) => *origin,
+            _ => unreachable!("compiled items always have an address"),
+        };
+        let next = origin + item.size() as u32;
+        // A variable may have been assigned again since the fixup was made, so only its value
+        // at the reference would be right, and that one is gone.
+        let is_variable = |name: &str| {
+            symbols.get(name).map(|symbol| &symbol.kind) == Some(&SymbolKind::Variable)
+        };
+        let value = fixup
+            .expr
+            .eval(
+                &|name| symbols.value(name).filter(|_| !is_variable(name)),
+                origin,
+            )
+            .map_err(|err| match err {
+                EvalError::Undefined(name) if is_variable(&name) => CompileError::at(
+                    ErrorKind::ForwardReference(name.clone()),
+                    fixup.line,
+                    fixup.column,
+                    format!(
+                        "value of `{}` must be known at this point, as `.set` can change it",
+                        name
+                    ),
+                ),
+                err => err.into_error(fixup.line, fixup.column),
+            })?;
+        let (bytes, value) = match item {
+            Item::Instruction(instruction) => (
+                &mut instruction.operands,
+                bank_address(&fixup.kind, origin, value),
+            ),
+            Item::Data(data) => (&mut data.bytes, value),
+        };
+        let encoded = if fixup.kind == FixupKind::Relative || fixup.kind == FixupKind::RelativeLong
+        {
+            branch_offset(
+                &fixup.kind,
+                next,
+                value,
+                &self.cpu,
+                fixup.line,
+                fixup.column,
+            )?
+        } else if fixup.kind == FixupKind::Immediate {
+            // The operand was sized by the register width in effect at the instruction.
+            let len = bytes.len() - fixup.offset;
+            immediate(value, len, fixup.column, fixup.line, &mut self.reporter)?
+        } else {
+            fixup.kind.encode(value, fixup.column, fixup.line)?
+        };
+        bytes[fixup.offset..fixup.offset + encoded.len()].copy_from_slice(&encoded);
+        Ok(())
+    }
+}
+
+/// How a value is written into the output.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum FixupKind {
+    Byte,
+    Word,
+    /// 24-bit address of the 65816.
+    Long,
+    Dword,
+    /// Branch displacement from the next instruction.
+    Relative,
+    /// 16-bit branch displacement from the next instruction, within the bank.
+    RelativeLong,
+    /// Immediate operand, which may be truncated to a byte.
+    Immediate,
+}
+
+impl FixupKind {
+    /// Encode `value` as little-endian bytes, checking that it fits.
+    ///
+    /// `column` and `line` locate the value for the error raised when it does not.
+    fn encode(&self, value: i64, column: usize, line: usize) -> Result<Vec<u8>> {
+        let (min, max, len) = match self {
+            FixupKind::Byte => (-0x80, 0xff, 1),
+            FixupKind::Word => (-0x8000, 0xffff, 2),
+            FixupKind::Long => (-0x80_0000, 0xff_ffff, 3),
+            FixupKind::Dword => (-0x8000_0000, 0xffff_ffff, 4),
+            FixupKind::Relative | FixupKind::RelativeLong => {
+                unreachable!("relative values are encoded by branch_offset")
+            }
+            FixupKind::Immediate => unreachable!("immediate values are encoded by immediate"),
+        };
+        if value < min || value > max {
+            return Err(CompileError::at(
+                ErrorKind::ValueOutOfRange,
+                line,
+                column,
+                "value out of range",
+            ));
+        }
+        Ok(value.to_le_bytes()[..len].to_vec())
     }
 }
 
+/// A value referencing symbols not defined yet when it was assembled.
+pub(crate) struct Fixup {
+    /// Index of the referencing item in the compiled output.
+    index: usize,
+    /// Offset of the patched bytes in the operands or data of the item.
+    offset: usize,
+    kind: FixupKind,
+    expr: Expr,
+    line: usize,
+    column: usize,
+    /// Macro expansions the value was written in, innermost first.
+    notes: Vec<Note>,
+    /// Scope the value was written in, which its names are looked up from.
+    scope: String,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use lint::Level;
     #[test]
     fn general_parse() {
         let mut asm = Asm6502::new(
-            "LDA #%0101\nSTA ($15,X)\nEOR ($2A),Y\nTAX\nJMP (label_a)\nADC $C001,X\nINC $F001,X\nLDA $01,X\nLDA ($01),Y\nBPL $2D\nLDY $02\nLDX label_a".to_string(),
+            "LDA #%0101\nSTA ($15,X)\nEOR ($2A),Y\nTAX\nJMP (label_a)\nADC $C001,X\nINC $F001,X\nLDA $01,X\nLDA ($01),Y\nBPL $802D\nLDY $02\nLDX label_a\nlabel_a\tBRK".to_string(),
         0x8000);
-        match asm.compile() {
-            Ok(instructions) => {
-                let mut bytes: Vec<u8> = vec![];
-                for instruction in instructions {
-                    let (_, mut buffer) = instruction.clone().into();
-                    bytes.append(&mut buffer);
-                    println!("{}", instruction);
-                }
-                let decomp = Asm6502::decompile(bytes.clone(), 0x8000);
-                println!("Bytecode: {:?}\n", bytes);
-                println!("Decompiled:\n");
-                for line in decomp {
-                    println!("{}", line);
-                }
+        asm.compile().unwrap();
+        let bytes = asm.to_binary(0);
+        assert_eq!(
+            bytes,
+            vec![
+                0xa9, 0x05, 0x81, 0x15, 0x51, 0x2a, 0xaa, 0x6c, 0x1b, 0x80, 0x7d, 0x01, 0xc0, 0xfe,
+                0x01, 0xf0, 0xb5, 0x01, 0xb1, 0x01, 0x10, 0x17, 0xa4, 0x02, 0xae, 0x1b, 0x80, 0x00
+            ]
+        );
+        assert_eq!(
+            Asm6502::decompile(bytes, 0x8000),
+            vec![
+                "8000\tLDA #$05",
+                "8002\tSTA ($15,X)",
+                "8004\tEOR ($2A),Y",
+                "8006\tTAX",
+                "8007\tJMP ($801B)",
+                "800A\tADC $C001,X",
+                "800D\tINC $F001,X",
+                "8010\tLDA $01,X",
+                "8012\tLDA ($01),Y",
+                "8014\tBPL $802D",
+                "8016\tLDY $02",
+                "8018\tLDX $801B",
+                "801B\tBRK"
+            ]
+        );
+    }
+    #[test]
+    fn forward_label_reference() {
+        let mut asm = Asm6502::new(
+            "JSR done\nloop\tINX\nJSR loop\ndone\tRTS".to_string(),
+            0x8000,
+        );
+        let bytes: Vec<u8> = asm
+            .compile()
+            .unwrap()
+            .into_iter()
+            .flat_map(|instruction| {
+                let (_, bytes): (InstructionAddress, Vec<u8>) = instruction.into();
+                bytes
+            })
+            .collect();
+        assert_eq!(bytes, vec![0x20, 0x07, 0x80, 0xe8, 0x20, 0x03, 0x80, 0x60]);
+        let mut asm = Asm6502::new("JSR nowhere".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err().remove(0).message,
+            "undefined symbol `nowhere`"
+        );
+    }
+    #[test]
+    fn branch_offsets() {
+        let mut asm = Asm6502::new(
+            "loop\tDEX\nBNE loop\nBEQ done\nNOP\ndone\tRTS".to_string(),
+            0x8000,
+        );
+        let items = asm.compile().unwrap();
+        let (_, bytes): (InstructionAddress, Vec<u8>) = items[1].clone().into();
+        assert_eq!(bytes, vec![0xd0, 0xfd]);
+        let (_, bytes): (InstructionAddress, Vec<u8>) = items[2].clone().into();
+        assert_eq!(bytes, vec![0xf0, 0x01]);
+        assert_eq!(items[1].to_string(), "8001\tBNE $8000");
+        let decomp = Asm6502::decompile(vec![0xd0, 0xfe, 0xf0, 0x7f], 0x8000);
+        assert_eq!(decomp, vec!["8000\tBNE $8000", "8002\tBEQ $8083"]);
+        let mut asm = Asm6502::new("BNE far\n".repeat(65) + "far\tRTS", 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err().remove(0).message,
+            "branch out of range"
+        );
+        let mut asm = Asm6502::new("BCC $8082".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err().remove(0).message,
+            "branch out of range"
+        );
+    }
+    #[test]
+    fn comments_and_label_lines() {
+        let mut asm = Asm6502::new(
+            "; counter\n\nstart:\n    LDX  #$05   ; loop count\n/* decrement\n   until zero */\nloop:  DEX\n  BNE loop\n  RTS\n".to_string(),
+            0x8000,
+        );
+        let items = asm.compile().unwrap();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[2].to_string(), "8003\tBNE $8002");
+        let mut asm = Asm6502::new("  NOP\n  FOO $01".to_string(), 0x8000);
+        let err = asm.compile().unwrap_err().remove(0);
+        assert_eq!((err.line, err.column), (2, Some(3)));
+        assert_eq!(err.kind, ErrorKind::UnknownMnemonic);
+        assert_eq!(err.message, "unknown mnemonic `FOO`");
+        let mut asm = Asm6502::new(
+            "beef NOP\nfade LDA #0\n  JMP beef\nC000\tJMP fade".to_string(),
+            0x8000,
+        );
+        let items = asm.compile().unwrap();
+        assert_eq!(items[2].to_string(), "8003\tJMP $8000");
+        assert_eq!(items[3].to_string(), "C000\tJMP $8001");
+        let mut asm = Asm6502::new(
+            "C000 NOP\nD000: NOP\n  JMP C000\n  JMP D000".to_string(),
+            0x8000,
+        );
+        asm.compile().unwrap();
+        let warnings: Vec<(usize, &ErrorKind)> = asm
+            .warnings
+            .iter()
+            .map(|warning| (warning.line, &warning.kind))
+            .collect();
+        assert_eq!(warnings, vec![(1, &ErrorKind::Lint(Lint::HexLabel))]);
+    }
+    #[test]
+    fn data_directives() {
+        let mut asm = Asm6502::new(
+            "  LDA table\n  RTS\ntable: .byte $01, 2, %11, 'A'\n.word table, end\n.dword $12345678\n.asciiz \"Hi\"\n.fill 3, $ea\n.res 2\nend:".to_string(),
+            0x8000,
+        );
+        let bytes: Vec<u8> = asm
+            .compile()
+            .unwrap()
+            .into_iter()
+            .flat_map(|item| {
+                let (_, bytes): (InstructionAddress, Vec<u8>) = item.into();
+                bytes
+            })
+            .collect();
+        assert_eq!(
+            bytes,
+            vec![
+                0xad, 0x04, 0x80, 0x60, 0x01, 0x02, 0x03, 0x41, 0x04, 0x80, 0x18, 0x80, 0x78, 0x56,
+                0x34, 0x12, 0x48, 0x69, 0x00, 0xea, 0xea, 0xea, 0x00, 0x00
+            ]
+        );
+        let mut asm = Asm6502::new(".byte 256".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err().remove(0).message,
+            "value out of range"
+        );
+        let mut asm = Asm6502::new(".fill later\nlater:".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err().remove(0).message,
+            "value of `later` must be known at this point"
+        );
+    }
+    #[test]
+    fn origins_and_segments() {
+        let mut asm = Asm6502::new(
+            "  JSR init\n  RTS\n.org $8010\ninit: LDA #$01\n  RTS\n*=$FFFC\n  .word init, $8000"
+                .to_string(),
+            0x8000,
+        );
+        asm.compile().unwrap();
+        let segments = asm.segments();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(
+            segments[1],
+            Segment {
+                start: 0x8010,
+                bytes: vec![0xa9, 0x01, 0x60]
             }
-            Err(err) => panic!("{}", err),
+        );
+        assert_eq!(segments[2].start, 0xfffc);
+        let binary = asm.to_binary(0xff);
+        assert_eq!(binary.len(), 0x8000);
+        assert_eq!(&binary[..6], &[0x20, 0x10, 0x80, 0x60, 0xff, 0xff]);
+        assert_eq!(&binary[0x7ffc..], &[0x10, 0x80, 0x00, 0x80]);
+        let mut asm = Asm6502::new("8000\tLDA #$01\n8001\tNOP".to_string(), 0x8000);
+        let err = asm.compile().unwrap_err().remove(0);
+        assert_eq!(
+            (err.line, err.message.as_str()),
+            (2, "overlapping memory regions")
+        );
+        let mut asm = Asm6502::new(".org $FFFF\n  JSR $1234".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err().remove(0).message,
+            "address out of range"
+        );
+    }
+    #[test]
+    fn expression_operands() {
+        let mut asm = Asm6502::new(
+            "  LDA #<vector\n  LDX #>vector\n  LDA table-1,X\n  STA ($10+2),Y\n  LDA (table+3)+3\n  JMP (vector)\n  CMP #'A'+1\ntable: .byte 1, 2\nvector: .word table+1".to_string(),
+            0x8000,
+        );
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![
+                0xa9, 0x13, 0xa2, 0x80, 0xbd, 0x10, 0x80, 0x91, 0x12, 0xad, 0x17, 0x80, 0x6c, 0x13,
+                0x80, 0xc9, 0x42, 0x01, 0x02, 0x12, 0x80
+            ]
+        );
+        let mut asm = Asm6502::new("  LDA #$100".to_string(), 0x8000);
+        let err = asm.compile().unwrap_err().remove(0);
+        assert_eq!(
+            (err.column, err.message.as_str()),
+            (Some(7), "immediate value does not fit in a byte")
+        );
+        let mut asm = Asm6502::new("  LDA $10/0".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err().remove(0).message,
+            "division by zero"
+        );
+    }
+    #[test]
+    fn accumulator_operands() {
+        let mut asm = Asm6502::new(
+            "  ASL\n  LSR A\n  ROL a\n  ROR\n  ASL $10".to_string(),
+            0x8000,
+        );
+        asm.compile().unwrap();
+        let bytes = asm.to_binary(0);
+        assert_eq!(bytes, vec![0x0a, 0x4a, 0x2a, 0x6a, 0x06, 0x10]);
+        assert_eq!(Asm6502::decompile(bytes.clone(), 0x8000)[1], "8001\tLSR");
+        let options = DecompileOptions {
+            accumulator_operand: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Asm6502::decompile_with(bytes, 0x8000, &options)[..2],
+            ["8000\tASL A", "8001\tLSR A"]
+        );
+        let mut asm = Asm6502::new("A = $10\n  LDA A".to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(asm.to_binary(0), vec![0xa5, 0x10]);
+        // Mnemonics are no more case-sensitive than the register names.
+        let mut asm = Asm6502::new("  lda #1\n  Rol a\n  sta.abs $10,x".to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(asm.to_binary(0), vec![0xa9, 0x01, 0x2a, 0x9d, 0x10, 0x00]);
+    }
+    #[test]
+    fn constants() {
+        let mut asm = Asm6502::new(
+            "SCREEN = $0400\nptr .equ $FB\ncount .set 2\n  LDA #count\n  STA ptr\n  STA SCREEN+1\n  count .set count+1\n  LDX #count\n  LDA (ptr),Y".to_string(),
+            0x8000,
+        );
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![0xa9, 0x02, 0x85, 0xfb, 0x8d, 0x01, 0x04, 0xa2, 0x03, 0xb1, 0xfb]
+        );
+        assert_eq!(asm.symbols.value("count"), Some(3));
+        assert_eq!(asm.symbols.get("ptr").unwrap().kind, SymbolKind::Constant);
+        let mut asm = Asm6502::new("ptr = $FB\nptr = $FC".to_string(), 0x8000);
+        let err = asm.compile().unwrap_err().remove(0);
+        assert_eq!(
+            (err.line, err.message.as_str()),
+            (2, "duplicate definition of `ptr`")
+        );
+        let mut asm = Asm6502::new("loop: NOP\nloop: NOP".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err().remove(0).kind,
+            ErrorKind::DuplicateLabel("loop".to_string())
+        );
+        let mut asm = Asm6502::new("ptr = $FB\nptr .set $FC".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err().remove(0).kind,
+            ErrorKind::DuplicateLabel("ptr".to_string())
+        );
+        // A variable assigned after its use has no single value to patch in.
+        let mut asm = Asm6502::new("  LDA #v\nv .set 1\nv .set 2".to_string(), 0x8000);
+        let err = asm.compile().unwrap_err().remove(0);
+        assert_eq!(err.kind, ErrorKind::ForwardReference("v".to_string()));
+        assert_eq!(err.line, 1);
+    }
+    #[test]
+    fn multiple_errors() {
+        let mut asm = Asm6502::new(
+            "start: LDA #$100\n  FOO\n  STX $10,X\n  BNE nowhere\n  LDA #'AB'\nstart: RTS\n"
+                .to_string(),
+            0x8000,
+        );
+        asm.file = Some("game.s".to_string());
+        let errors = asm.compile().unwrap_err();
+        let kinds: Vec<(usize, ErrorKind)> = errors
+            .iter()
+            .map(|err| (err.line, err.kind.clone()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (1, ErrorKind::Lint(Lint::TruncatedImmediate)),
+                (2, ErrorKind::UnknownMnemonic),
+                (3, ErrorKind::InvalidAddressingMode),
+                (4, ErrorKind::UndefinedSymbol("nowhere".to_string())),
+                (5, ErrorKind::SyntaxError),
+                (6, ErrorKind::DuplicateLabel("start".to_string())),
+            ]
+        );
+        assert_eq!(errors[1].source.as_deref(), Some("  FOO"));
+        assert_eq!(
+            errors[1].to_string(),
+            "compile error in game.s on line 2, column 3: unknown mnemonic `FOO`"
+        );
+    }
+    #[test]
+    fn lints() {
+        let source = "start: LDA #$1234\n  JMP ($10FF)\n  *NOP\nunused:\ninx: BNE start\n.org $80FD\nfar: BEQ next\n  JMP inx\nnext: JMP far";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::Lint(Lint::TruncatedImmediate));
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.lints.set(Lint::TruncatedImmediate, Level::Warn);
+        asm.lints.set(Lint::BranchPageCrossing, Level::Warn);
+        asm.compile().unwrap();
+        assert_eq!(asm.to_binary(0)[..2], [0xa9, 0x34]);
+        let warnings: Vec<(usize, &str)> = asm
+            .warnings
+            .iter()
+            .map(|warning| match &warning.kind {
+                ErrorKind::Lint(lint) => (warning.line, lint.name()),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                (1, "truncated-immediate"),
+                (2, "jmp-indirect-page-wrap"),
+                (3, "undocumented-opcode"),
+                (4, "unused-label"),
+                (5, "label-shadows-mnemonic"),
+                (7, "branch-page-crossing"),
+            ]
+        );
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.lints.set(Lint::TruncatedImmediate, Level::Allow);
+        asm.lints.set_by_name("warnings", Level::Deny).unwrap();
+        assert_eq!(asm.compile().unwrap_err().len(), 4);
+        assert!(asm
+            .lints
+            .set_by_name("unused-labels", Level::Allow)
+            .is_err());
+    }
+    #[test]
+    fn illegal_opcodes() {
+        let source = "  LAX $10\n  ISC $1234,X\n  DCM ($20),Y\n  SBX #$04\n  NOP $10\n  *SAX $30";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(errors.len(), 5);
+        assert_eq!(
+            errors[0].message,
+            "undocumented opcode `LAX` requires illegal opcodes to be allowed"
+        );
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.cpu.allow_illegal = true;
+        asm.compile().unwrap();
+        let bytes = asm.to_binary(0);
+        assert_eq!(
+            bytes,
+            vec![0xa7, 0x10, 0xff, 0x34, 0x12, 0xd3, 0x20, 0xcb, 0x04, 0x04, 0x10, 0x87, 0x30]
+        );
+        assert_eq!(
+            Asm6502::decompile(bytes.clone(), 0x8000)[..2],
+            ["8000\t.byte $A7", "8001\tBPL $8002"]
+        );
+        let options = DecompileOptions {
+            cpu: CpuOptions {
+                allow_illegal: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            Asm6502::decompile_with(bytes, 0x8000, &options),
+            vec![
+                "8000\tLAX $10",
+                "8002\tISB $1234,X",
+                "8005\tDCP ($20),Y",
+                "8007\tAXS #$04",
+                "8009\tNOP $10",
+                "800B\tSAX $30"
+            ]
+        );
+    }
+    #[test]
+    fn cmos_65c02() {
+        let source = "  BRA next\n  PHX\n  STZ $10\n  STZ $1234,X\nnext:\n  TSB $20\n  INC A\n  DEC\n  BIT #$80\n  LDA ($10)\n  JMP ($1234,X)\n  JMP ($12FF)";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(errors[0].message, "`BRA` is not available on the 6502");
+        assert!(errors
+            .iter()
+            .any(|err| err.message == "`LDA` has no indirect mode on the 6502"));
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.cpu.model = CpuModel::Cmos65C02;
+        asm.compile().unwrap();
+        // Indirect jumps no longer wrap around the page on the 65C02.
+        assert!(asm.warnings.is_empty());
+        let bytes = asm.to_binary(0);
+        assert_eq!(
+            bytes,
+            vec![
+                0x80, 0x06, 0xda, 0x64, 0x10, 0x9e, 0x34, 0x12, 0x04, 0x20, 0x1a, 0x3a, 0x89, 0x80,
+                0xb2, 0x10, 0x7c, 0x34, 0x12, 0x6c, 0xff, 0x12
+            ]
+        );
+        let options = DecompileOptions {
+            cpu: CpuOptions::new(CpuModel::Cmos65C02),
+            ..Default::default()
+        };
+        assert_eq!(
+            Asm6502::decompile_with(bytes.clone(), 0x8000, &options)[..3],
+            ["8000\tBRA $8008", "8002\tPHX", "8003\tSTZ $10"]
+        );
+        // The NMOS decoder doesn't know the CMOS opcodes.
+        assert_eq!(Asm6502::decompile(bytes, 0x8000)[0], "8000\t.byte $80");
+
+        let mut asm = Asm6502::new("  LAX $10\n  *SAX $30".to_string(), 0x8000);
+        asm.cpu = CpuOptions::new(CpuModel::Cmos65C02);
+        asm.cpu.allow_illegal = true;
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "`LAX` is not available on the 65c02");
+    }
+    #[test]
+    fn bit_instructions() {
+        let source = "loop:\n  SMB3 $10\n  BBR3 $10,done\n  BBS7 flags,loop\n  WAI\ndone:\n  RMB3 $10\nflags = $20";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.cpu.model = CpuModel::Rockwell65C02;
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "`WAI` is not available on the r65c02");
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.cpu.model = CpuModel::Wdc65C02;
+        asm.compile().unwrap();
+        let bytes = asm.to_binary(0);
+        assert_eq!(
+            bytes,
+            vec![0xb7, 0x10, 0x3f, 0x10, 0x04, 0xff, 0x20, 0xf8, 0xcb, 0x37, 0x10]
+        );
+        let options = DecompileOptions {
+            cpu: CpuOptions::new(CpuModel::Wdc65C02),
+            ..Default::default()
+        };
+        assert_eq!(
+            Asm6502::decompile_with(bytes, 0x8000, &options),
+            vec![
+                "8000\tSMB3 $10",
+                "8002\tBBR3 $10,$8009",
+                "8005\tBBS7 $20,$8000",
+                "8008\tWAI",
+                "8009\tRMB3 $10"
+            ]
+        );
+        let mut asm = Asm6502::new("  BBR0 $10".to_string(), 0x8000);
+        asm.cpu.model = CpuModel::Wdc65C02;
+        assert_eq!(
+            asm.compile().unwrap_err()[0].message,
+            "expected a zero page address and a branch target"
+        );
+    }
+    #[test]
+    fn wdc_65816() {
+        let source = "start:\n  REP #$30\n  .a16\n  .i16\n  LDA #$1234\n  LDY #count\n  SEP #$20\n  .a8\n  LDA #$12\n  LDA $123456,X\n  LDA [$10],Y\n  STA $03,S\n  LDA ($05,S),Y\n  MVN $01,$02\n  JSL far\n  BRL start\n  JML [$1234]\nfar:\n  RTL\ncount = $0100";
+        let mut asm = Asm6502::new(source.to_string(), 0x01_8000);
+        asm.cpu.model = CpuModel::Wdc65816;
+        asm.compile().unwrap();
+        let bytes = asm.to_binary(0);
+        assert_eq!(
+            bytes,
+            vec![
+                0xc2, 0x30, 0xa9, 0x34, 0x12, 0xa0, 0x00, 0x01, 0xe2, 0x20, 0xa9, 0x12, 0xbf, 0x56,
+                0x34, 0x12, 0xb7, 0x10, 0x83, 0x03, 0xb3, 0x05, 0x54, 0x02, 0x01, 0x22, 0x23, 0x80,
+                0x01, 0x82, 0xe0, 0xff, 0xdc, 0x34, 0x12, 0x6b
+            ]
+        );
+        let options = DecompileOptions {
+            cpu: CpuOptions::new(CpuModel::Wdc65816),
+            ..Default::default()
+        };
+        let listing = Asm6502::decompile_with(bytes.clone(), 0x01_8000, &options);
+        assert_eq!(
+            listing,
+            vec![
+                "018000\tREP #$30",
+                "018002\t.a16",
+                "018002\t.i16",
+                "018002\tLDA #$1234",
+                "018005\tLDY #$0100",
+                "018008\tSEP #$20",
+                "01800A\t.a8",
+                "01800A\tLDA #$12",
+                "01800C\tLDA $123456,X",
+                "018010\tLDA [$10],Y",
+                "018012\tSTA $03,S",
+                "018014\tLDA ($05,S),Y",
+                "018016\tMVN $01,$02",
+                "018019\tJSL $018023",
+                "01801D\tBRL $018000",
+                "018020\tJML [$1234]",
+                "018023\tRTL"
+            ]
+        );
+        // The hints make the listing assemble back to the same bytes.
+        let mut asm = Asm6502::new(listing.join("\n"), 0);
+        asm.cpu.model = CpuModel::Wdc65816;
+        asm.compile().unwrap();
+        assert_eq!(asm.to_binary(0), bytes);
+
+        // Jumps and absolute operands take the bank of the instruction, and forward
+        // references use long addressing where the instruction has it.
+        let source =
+            "start:\n  NOP\n  JMP start\n  JSR sub\n  LDA table\n  LDA table,Y\nsub:\n  RTS\ntable:\n  .byte 1";
+        let mut asm = Asm6502::new(source.to_string(), 0x01_8000);
+        asm.cpu.model = CpuModel::Wdc65816;
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![
+                0xea, 0x4c, 0x00, 0x80, 0x20, 0x0e, 0x80, 0xaf, 0x0f, 0x80, 0x01, 0xb9, 0x0f, 0x80,
+                0x60, 0x01
+            ]
+        );
+        let mut asm = Asm6502::new("  JMP $028000".to_string(), 0x01_8000);
+        asm.cpu.model = CpuModel::Wdc65816;
+        assert_eq!(asm.compile().unwrap_err()[0].message, "value out of range");
+
+        // The program counter wraps around within the bank.
+        let options = DecompileOptions {
+            cpu: CpuOptions::new(CpuModel::Wdc65816),
+            ..Default::default()
+        };
+        assert_eq!(
+            Asm6502::decompile_with(vec![0x80, 0x00], 0xfffe, &options),
+            vec!["FFFE\tBRA $0000"]
+        );
+        let mut cpu = CpuOptions::new(CpuModel::Wdc65816);
+        cpu.wide_accumulator = true;
+        let instruction =
+            Instruction::from_source_line_with(&SymbolTable::new(), "LDA #$1234", 0x8000, &cpu)
+                .unwrap();
+        assert_eq!(instruction.operands, vec![0x34, 0x12]);
+        let mut asm = Asm6502::new("  .a16".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err()[0].message,
+            "`.a16` is only available on the 65816"
+        );
+    }
+    #[test]
+    fn ricoh_2a03() {
+        let source = "  SED\n  LDA #$80\n  STA PPUCTRL\n  LAX $10\n  STA OAMDMA";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::UndefinedSymbol("PPUCTRL".to_string())
+        );
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.cpu.model = CpuModel::Ricoh2A03;
+        asm.cpu.allow_illegal = true;
+        asm.nes_symbols = true;
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![0xf8, 0xa9, 0x80, 0x8d, 0x00, 0x20, 0xa7, 0x10, 0x8d, 0x14, 0x40]
+        );
+        assert_eq!(asm.warnings.len(), 1);
+        assert_eq!(asm.warnings[0].kind, ErrorKind::Lint(Lint::DecimalMode));
+        let mut asm = Asm6502::new("PPUCTRL = $10".to_string(), 0x8000);
+        asm.nes_symbols = true;
+        assert_eq!(
+            asm.compile().unwrap_err()[0].message,
+            "`PPUCTRL` is a predefined symbol"
+        );
+    }
+    #[test]
+    fn macros() {
+        let source = ".macro store16 value, ptr\n  LDA #<value\n  STA ptr\n  LDA #>value\n  STA ptr+1\n.endmacro\n.macro wait count\n  LDX #count\nloop:\n  DEX\n  BNE loop\n.endmacro\nstart:\n  store16 $1234, $10\n  wait 5\nwait 3\n  JMP start";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![
+                0xa9, 0x34, 0x85, 0x10, 0xa9, 0x12, 0x85, 0x11, 0xa2, 0x05, 0xca, 0xd0, 0xfd, 0xa2,
+                0x03, 0xca, 0xd0, 0xfd, 0x4c, 0x00, 0x80
+            ]
+        );
+        // Commas inside parentheses and the index of an indirect operand belong to the argument.
+        let source = ".macro ld addr\n  LDA addr\n.endmacro\n  ld ($10),Y\n  ld ($20,X)";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(asm.to_binary(0), vec![0xb1, 0x10, 0xa1, 0x20]);
+        // A register after a parenthesized expression is an argument of its own.
+        let source = "a = 1\nb = 2\n.macro m v, r\n  LDA v+1,r\n.endmacro\n  m (a+b), X";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(asm.to_binary(0), vec![0xb5, 0x04]);
+
+        // Errors point at the definition, with a note for each expansion.
+        let source = ".macro load value\n  LDA #value\n.endmacro\n.macro outer\n  load $100\n  load later\n.endmacro\n  outer\n  load";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].line, 2);
+        let notes: Vec<(usize, &str)> = errors[0]
+            .notes
+            .iter()
+            .map(|note| (note.line, note.message.as_str()))
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                (5, "in this expansion of macro `load`"),
+                (8, "in this expansion of macro `outer`")
+            ]
+        );
+        // Forward references resolved at the end keep the trace too.
+        assert_eq!(
+            errors[1].kind,
+            ErrorKind::UndefinedSymbol("later".to_string())
+        );
+        assert_eq!(errors[1].notes[0].line, 6);
+        assert_eq!(
+            errors[2].message,
+            "macro `load` takes 1 argument(s) but 0 were given"
+        );
+
+        let mut asm = Asm6502::new(
+            ".macro forever\n  forever\n.endmacro\n  forever".to_string(),
+            0x8000,
+        );
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .message
+            .starts_with("macro expansions nested deeper than 64"));
+        let mut asm = Asm6502::new(".macro open\n  NOP".to_string(), 0x8000);
+        assert_eq!(
+            asm.compile().unwrap_err()[0].message,
+            "macro `open` is missing its `.endmacro`"
+        );
+    }
+    #[test]
+    fn conditional_assembly() {
+        let source = "DEBUG = 0\nLEVEL = 2\n.if DEBUG\n  BRK\n.elseif LEVEL > 1\n  .if LEVEL == 3\n  NOP\n  .else\n  INX\n  .endif\n.else\n  INY\n.endif\n.ifdef LEVEL\n  DEX\n.endif\n.ifndef MISSING\n  DEY\n.endif";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(asm.to_binary(0), vec![0xe8, 0xca, 0x88]);
+
+        // Conditions can't wait for symbols defined further down.
+        let source =
+            ".if later\n  NOP\n.endif\n.ifdef later\n  NOP\n.endif\nlater = 1\n.else\n.if 1\n  NOP";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        let messages: Vec<(usize, &str)> = errors
+            .iter()
+            .map(|err| (err.line, err.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (1, "value of `later` must be known at this point"),
+                (
+                    4,
+                    "`.ifdef` tests `later` before it is defined, so it is always taken as undefined"
+                ),
+                (8, "`.else` without `.if`"),
+                (9, "conditional block is missing its `.endif`"),
+            ]
+        );
+        assert_eq!(
+            errors[1].kind,
+            ErrorKind::ForwardReference("later".to_string())
+        );
+        assert_eq!(errors[1].notes[0].line, 7);
+    }
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("asm_6502_includes_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("main.s"),
+            "  .include \"a.s\"\n  .incbin \"data.bin\", 1, 2\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib/a.s"), "start:\n  .include \"b.s\"\n").unwrap();
+        std::fs::write(dir.join("lib/b.s"), "  LDA #1\n  JMP start\n").unwrap();
+        std::fs::write(dir.join("data.bin"), [1, 2, 3, 4]).unwrap();
+        let main = dir.join("main.s").display().to_string();
+        let mut asm = Asm6502::new(std::fs::read_to_string(&main).unwrap(), 0x8000);
+        asm.file = Some(main.clone());
+        asm.include_paths = vec![dir.join("lib")];
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![0xa9, 0x01, 0x4c, 0x00, 0x80, 0x02, 0x03]
+        );
+
+        // Errors are located in the included file, with the stack of includes leading to it.
+        std::fs::write(dir.join("lib/b.s"), "  JMP nowhere\n  .include \"a.s\"\n").unwrap();
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        let b = dir.join("lib").join("b.s").display().to_string();
+        assert_eq!(errors[0].file.as_ref(), Some(&b));
+        assert_eq!(errors[0].line, 1);
+        assert_eq!(errors[0].source.as_deref(), Some("  JMP nowhere"));
+        let stack: Vec<(usize, &str)> = errors[0]
+            .notes
+            .iter()
+            .map(|note| (note.line, note.source.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            stack,
+            vec![(2, "  .include \"b.s\""), (1, "  .include \"a.s\"")]
+        );
+        assert_eq!(errors[0].notes[1].file.as_ref(), Some(&main));
+        assert_eq!(errors[1].kind, ErrorKind::IncludeCycle);
+        assert_eq!(errors[1].line, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn local_labels() {
+        let source = "first:\n  LDX #<second\n@loop:\n  DEX\n  BNE @loop\n  BEQ .done\n.done:\nsecond:\n_loop:\n  JMP _loop\n@loop: JMP @loop\n1:\n  BNE 1f\n  BEQ 1b\n1:\n- INX\n  BNE -\n  BEQ +\n+ BEQ +\n+ JMP first";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![
+                0xa2, 0x07, 0xca, 0xd0, 0xfd, 0xf0, 0x00, 0x4c, 0x07, 0x80, 0x4c, 0x0a, 0x80, 0xd0,
+                0x02, 0xf0, 0xfc, 0xe8, 0xd0, 0xfd, 0xf0, 0x00, 0xf0, 0x00, 0x4c, 0x00, 0x80
+            ]
+        );
+        assert!(asm.warnings.is_empty());
+        assert_eq!(asm.symbols.value("second::_loop"), Some(0x8007));
+
+        let mut asm = Asm6502::new("first:\n  JMP @missing\n  BNE 2f".to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::UndefinedSymbol("first::@missing".to_string())
+        );
+        assert_eq!(errors.len(), 2);
+    }
+    #[test]
+    fn scopes() {
+        let source = "x = 1\n.proc draw\n  LDA #x\nloop:\n  DEX\n  BNE loop\n@done: RTS\n.scope tables\nx = 2\n  .byte x, ::x\n.endscope\n.endproc\n.proc clear\nloop:\n  JMP draw::loop\n.endproc\n  JSR draw\n  JSR clear\n  JMP clear::loop\n  .word draw::loop::@done, draw::tables::x";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![
+                0xa9, 0x01, 0xca, 0xd0, 0xfd, 0x60, 0x02, 0x01, 0x4c, 0x02, 0x80, 0x20, 0x00, 0x80,
+                0x20, 0x08, 0x80, 0x4c, 0x08, 0x80, 0x05, 0x80, 0x02, 0x00
+            ]
+        );
+        assert!(asm.warnings.is_empty());
+        assert_eq!(
+            asm.symbols.scopes(),
+            vec!["", "clear", "draw", "draw::loop", "draw::tables"]
+        );
+        let members: Vec<&str> = asm
+            .symbols
+            .members("draw")
+            .iter()
+            .map(|(name, _)| *name)
+            .collect();
+        assert_eq!(members, vec!["loop"]);
+
+        // A name used before a symbol of the same name is defined in an inner scope.
+        let source = "x = 1\n.proc draw\n  LDA #x\nx = 2\n.endproc\n.scope open\n.endproc";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        let messages: Vec<(usize, &str)> = errors
+            .iter()
+            .map(|err| (err.line, err.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    3,
+                    "`x` refers to `x` here, but to `draw::x` once it is defined"
+                ),
+                (6, "`.scope open` is missing its `.endscope`"),
+                (7, "`.endproc` closing `.scope open`"),
+            ]
+        );
+    }
+    #[test]
+    fn repetitions() {
+        let source = ".rept 3\n  ASL\n.endr\n.for i = 0, i < 4, i++\n  .byte i * i\n.endr\n.for i = 1, i <= 8, i = i * 2\nloop: DEX\n  BNE loop\n.endr\n.for i = 1, i >= 0, i--\n  .word table + i\n.endfor\ntable: .byte 0";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.compile().unwrap();
+        let mut expected = vec![0x0a, 0x0a, 0x0a, 0x00, 0x01, 0x04, 0x09];
+        for _ in 0..4 {
+            expected.extend([0xca, 0xd0, 0xfd].iter());
         }
+        expected.extend([0x18, 0x80, 0x17, 0x80, 0x00].iter());
+        assert_eq!(asm.to_binary(0), expected);
+        assert!(asm.warnings.is_empty());
+
+        let source = ".for i = 0, i < later, i++\n.endr\n.for i = 0, j < 3, j++\n.endr\n.rept 2\n  LDA #i\n.endr\n.endr\n.rept 2\nlater = 3";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        let messages: Vec<(usize, &str)> = errors
+            .iter()
+            .map(|err| (err.line, err.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (1, "value of `later` must be known at this point"),
+                (
+                    3,
+                    "expected a step of `i`, such as `i++`, `i += 2` or `i = i * 2`"
+                ),
+                (6, "undefined symbol `i`"),
+                (6, "undefined symbol `i`"),
+                (8, "`.endr` without `.rept` or `.for`"),
+                (9, "`.rept` is missing its `.endr`"),
+            ]
+        );
+        assert_eq!(errors[2].notes[0].message, "in iteration 1 of this `.rept`");
+    }
+    #[test]
+    fn encodings() {
+        let source = "  .text \"Hi\"\n  .petscii \"Hi\\n\"\n  .screen \"Hi@\"\n  .atascii \"a|\\n\"\n  .antic \"A a\"\n  LDA #'A'\n.charmap 'A', $01\n.charmap '0', '9', $10\n  LDA #'A'\n  .text \"A5\"\n.encoding petscii\n  LDA #'a'\n  .byte 'A', \"b\"";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![
+                0x48, 0x69, 0xc8, 0x49, 0x0d, 0x48, 0x09, 0x00, 0x61, 0x7c, 0x9b, 0x21, 0x00, 0x61,
+                0xa9, 0x41, 0xa9, 0x01, 0x01, 0x15, 0xa9, 0x41, 0xc1, 0x42
+            ]
+        );
+
+        let source = "  .petscii \"{\"\n  .screen \"\\n\"\n.encoding ebcdic\n.charmap 'A', $100";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|err| err.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "`{` has no code in petscii",
+                "`\\n` has no code in screen",
+                "unknown encoding `ebcdic`, expected one of ascii, petscii, screen, atascii, antic",
+                "codes out of range of a byte",
+            ]
+        );
+    }
+    #[test]
+    fn operand_widths() {
+        let source = "  LDA $0005\n  LDA a:$05\n  LDA.abs $05,X\n  LDA $05,Y\n  LDX $05,Y\n  LDA z:later\n  LDA later\n  STA <later\n  JMP $0005\nlater:\n  LDA later";
+        let mut asm = Asm6502::new(source.to_string(), 0x0000);
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![
+                0xa5, 0x05, 0xad, 0x05, 0x00, 0xbd, 0x05, 0x00, 0xb9, 0x05, 0x00, 0xb6, 0x05, 0xa5,
+                0x17, 0xad, 0x17, 0x00, 0x85, 0x17, 0x4c, 0x05, 0x00, 0xa5, 0x17
+            ]
+        );
+        // A constant defined further down is sized like a label, unless the instruction only has
+        // a zero page form.
+        let source = "  STX fwd,Y\n  STY fwd,X\n  LDA ZP\nfwd = $20\nZP = $10";
+        let mut asm = Asm6502::new(source.to_string(), 0x0000);
+        asm.compile().unwrap();
+        assert_eq!(
+            asm.to_binary(0),
+            vec![0x96, 0x20, 0x94, 0x20, 0xad, 0x10, 0x00]
+        );
+        // Disassembly keeps absolute addresses in the zero page absolute.
+        let instruction =
+            Instruction::from_source_line(&SymbolTable::new(), "LDA a:$05,Y", 0x8000).unwrap();
+        assert_eq!(String::from(&instruction), "LDA a:$0005,Y");
+
+        let source =
+            "  LDA.zp $1234\n  LDA z:$12,Y\n  BNE.abs $12\n  LDA.foo $12\n  STX big,Y\nbig = $1234";
+        let mut asm = Asm6502::new(source.to_string(), 0x8000);
+        let errors = asm.compile().unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|err| err.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "value out of range",
+                "invalid addressing mode for instruction",
+                "`.abs` only applies to an address, indexed or not",
+                "unknown mnemonic `LDA.foo`",
+                "value out of range",
+            ]
+        );
     }
 }
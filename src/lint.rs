@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use crate::error::{CompileError, ErrorKind};
+use crate::Result;
+
+/// A suspicious construct that is reported as a warning, or as an error once denied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A label that no operand refers to.
+    UnusedLabel,
+    /// A label named like an instruction, such as `inx:`.
+    LabelShadowsMnemonic,
+    /// An undocumented opcode written with a `*` prefix.
+    UndocumentedOpcode,
+    /// An immediate operand that does not fit in a byte and is cut to its low byte.
+    TruncatedImmediate,
+    /// `JMP ($xxFF)`, which reads the high byte of the target from $xx00 on the 6502.
+    JmpIndirectPageWrap,
+    /// A taken branch to another page, which costs an extra cycle.
+    BranchPageCrossing,
+    /// `SED` on a CPU without decimal mode, such as the 2A03 of the NES.
+    DecimalMode,
+    /// A label made of hex digits, such as `C000`, which reads like the address of the line.
+    HexLabel,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 8] = [
+        Lint::UnusedLabel,
+        Lint::LabelShadowsMnemonic,
+        Lint::UndocumentedOpcode,
+        Lint::TruncatedImmediate,
+        Lint::JmpIndirectPageWrap,
+        Lint::BranchPageCrossing,
+        Lint::DecimalMode,
+        Lint::HexLabel,
+    ];
+    /// Identifier used to allow or deny the lint.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedLabel => "unused-label",
+            Lint::LabelShadowsMnemonic => "label-shadows-mnemonic",
+            Lint::UndocumentedOpcode => "undocumented-opcode",
+            Lint::TruncatedImmediate => "truncated-immediate",
+            Lint::JmpIndirectPageWrap => "jmp-indirect-page-wrap",
+            Lint::BranchPageCrossing => "branch-page-crossing",
+            Lint::DecimalMode => "decimal-mode",
+            Lint::HexLabel => "hex-label",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Lint::ALL.iter().copied().find(|lint| lint.name() == name)
+    }
+    pub fn default_level(&self) -> Level {
+        match self {
+            Lint::TruncatedImmediate => Level::Deny,
+            Lint::BranchPageCrossing => Level::Allow,
+            _ => Level::Warn,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// Level of every lint, starting from their defaults.
+#[derive(Clone, Debug, Default)]
+pub struct LintLevels {
+    levels: HashMap<Lint, Level>,
+    /// Deny every lint that would otherwise be a warning.
+    pub warnings_as_errors: bool,
+}
+
+impl LintLevels {
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        self.levels.insert(lint, level);
+    }
+    /// Set a lint by its name, where denying `warnings` turns every warning into an error.
+    pub fn set_by_name(&mut self, name: &str, level: Level) -> std::result::Result<(), String> {
+        if name == "warnings" {
+            self.warnings_as_errors = level == Level::Deny;
+            return Ok(());
+        }
+        match Lint::from_name(name) {
+            Some(lint) => {
+                self.set(lint, level);
+                Ok(())
+            }
+            None => Err(format!("unknown lint `{}`", name)),
+        }
+    }
+    pub fn level(&self, lint: Lint) -> Level {
+        match self.levels.get(&lint).copied() {
+            Some(level) => level,
+            None => lint.default_level(),
+        }
+    }
+    /// Level a lint is reported at, once warnings may have been turned into errors.
+    fn effective_level(&self, lint: Lint) -> Level {
+        match self.level(lint) {
+            Level::Warn if self.warnings_as_errors => Level::Deny,
+            level => level,
+        }
+    }
+}
+
+/// Collects the warnings of a compilation.
+pub(crate) struct Reporter {
+    pub levels: LintLevels,
+    pub warnings: Vec<CompileError>,
+}
+
+impl Reporter {
+    pub fn new(levels: LintLevels) -> Self {
+        Reporter {
+            levels,
+            warnings: vec![],
+        }
+    }
+    /// Report `lint` at `line` and `column`, as an error if the lint is denied.
+    pub fn report(
+        &mut self,
+        lint: Lint,
+        line: usize,
+        column: Option<usize>,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        let err = CompileError {
+            column,
+            ..CompileError::new(ErrorKind::Lint(lint), line, message)
+        };
+        match self.levels.effective_level(lint) {
+            Level::Allow => Ok(()),
+            Level::Warn => {
+                self.warnings.push(err);
+                Ok(())
+            }
+            Level::Deny => Err(err),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+
+use crate::data::split_arguments;
+use crate::error::{CompileError, ErrorKind};
+use crate::lexer::{Line, Token, TokenKind};
+use crate::parser::Statement;
+use crate::{InstructionAddress, Result};
+
+/// Deepest nesting of macro expansions, which stops a macro invoking itself forever.
+pub const EXPANSION_LIMIT: usize = 64;
+
+/// Most iterations of a `.rept` or `.for` block, which stops a `.for` that never ends.
+pub const REPETITION_LIMIT: i64 = 0x10000;
+
+/// A macro defined with `.macro name param, … .endmacro`.
+#[derive(Clone, Debug)]
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    /// Lines between `.macro` and `.endmacro`.
+    pub body: Vec<Line>,
+    /// Line of the `.macro` directive.
+    pub line: usize,
+}
+
+impl Macro {
+    /// Start a macro from the operand of its `.macro` directive, with an empty body.
+    pub(crate) fn from_statement(statement: &Statement) -> Result<Self> {
+        let line = statement.line;
+        let directive = statement
+            .mnemonic
+            .as_ref()
+            .expect("directive statements have a mnemonic");
+        let (name, params) = match statement.operand.split_first() {
+            Some((name, params)) if name.kind == TokenKind::Identifier => (name, params),
+            _ => {
+                return Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    line,
+                    directive.column,
+                    "expected a macro name",
+                ))
+            }
+        };
+        let params = split_arguments(params)
+            .into_iter()
+            .map(|param| match param {
+                [name] if name.kind == TokenKind::Identifier => Ok(name.text.clone()),
+                _ => Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    line,
+                    param.first().map_or(directive.column, |token| token.column),
+                    "expected a parameter name",
+                )),
+            })
+            .collect::<Result<Vec<String>>>()?;
+        Ok(Macro {
+            name: name.text.clone(),
+            params,
+            body: vec![],
+            line,
+        })
+    }
+    /// Lines of the body with the parameters replaced by `arguments`.
+    ///
+    /// Symbols defined in the body are renamed with the number of the expansion, so that
+    /// every expansion has its own labels.
+    pub fn expand(&self, arguments: &[&[Token]], expansion: usize) -> Vec<Line> {
+        let defined: HashSet<String> = self
+            .body
+            .iter()
+            .filter_map(|line| match Statement::parse(line) {
+                Ok(Statement {
+                    address: InstructionAddress::Label(name),
+                    ..
+                }) => Some(name),
+                _ => None,
+            })
+            .collect();
+        self.body
+            .iter()
+            .map(|line| {
+                let mut tokens = vec![];
+                for token in &line.tokens {
+                    let param = self.params.iter().position(|param| {
+                        token.kind == TokenKind::Identifier && *param == token.text
+                    });
+                    if let Some(index) = param {
+                        // Arguments take the place of the parameter, errors point at it.
+                        tokens.extend(arguments[index].iter().map(|argument| Token {
+                            column: token.column,
+                            ..argument.clone()
+                        }));
+                    } else if token.kind == TokenKind::Identifier && defined.contains(&token.text) {
+                        tokens.push(Token {
+                            text: format!("{}#{}", token.text, expansion),
+                            ..token.clone()
+                        });
+                    } else {
+                        tokens.push(token.clone());
+                    }
+                }
+                Line {
+                    number: line.number,
+                    tokens,
+                    tabbed: line.tabbed,
+                }
+            })
+            .collect()
+    }
+}
+
+/// How a `.rept` or `.for` block repeats its body.
+#[derive(Clone, Debug)]
+pub enum Repeat {
+    /// `.rept count`.
+    Count(i64),
+    /// `.for variable = start, condition, step`, where `next` gives the value of the variable
+    /// for the next iteration.
+    For {
+        variable: String,
+        start: i64,
+        condition: Vec<Token>,
+        next: Vec<Token>,
+    },
+}
+
+/// A `.rept` or `.for` block, repeated once its `.endr` is reached.
+#[derive(Clone, Debug)]
+pub struct Repetition {
+    /// Statement opening the block.
+    pub statement: Statement,
+    pub repeat: Repeat,
+    /// Lines between the opening directive and `.endr`.
+    pub body: Vec<Line>,
+}
+
+/// Split the arguments of a macro taking `params` arguments on commas, keeping the index
+/// register of an indirect operand with it, as in `ld ($10),Y`, when there are more arguments
+/// than parameters. `m (a+b), X` gives `m` two arguments if it takes two.
+pub(crate) fn arguments(operand: &[Token], params: usize) -> Vec<&[Token]> {
+    let split = split_arguments(operand);
+    let mut merges = split.len().saturating_sub(params);
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    let mut start = 0;
+    for argument in split {
+        let end = start + argument.len();
+        let register = matches!(argument, [register] if register.kind == TokenKind::Identifier
+            && ["X", "Y", "S"].iter().any(|name| register.text.eq_ignore_ascii_case(name)));
+        match ranges.last_mut() {
+            Some((first, last)) if merges > 0 && register && bracketed(&operand[*first..*last]) => {
+                merges -= 1;
+                *last = end
+            }
+            _ => ranges.push((start, end)),
+        }
+        start = end + 1;
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| &operand[start..end])
+        .collect()
+}
+
+/// Check whether `tokens` are a single group in parentheses or brackets, as the `($10)` of
+/// `($10),Y`.
+fn bracketed(tokens: &[Token]) -> bool {
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        if token.is_symbol("(") || token.is_symbol("[") {
+            depth += 1;
+        } else if token.is_symbol(")") || token.is_symbol("]") {
+            depth -= 1;
+        }
+        if depth == 0 {
+            return index > 0 && index == tokens.len() - 1;
+        }
+    }
+    false
+}
+
+/// Tokens of an integer literal, which may be negative.
+pub(crate) fn literal(value: i64, column: usize) -> Vec<Token> {
+    let number = Token::new(TokenKind::Number, value.unsigned_abs().to_string(), column);
+    if value < 0 {
+        vec![
+            Token::new(TokenKind::Symbol, "(".to_string(), column),
+            Token::new(TokenKind::Symbol, "-".to_string(), column),
+            number,
+            Token::new(TokenKind::Symbol, ")".to_string(), column),
+        ]
+    } else {
+        vec![number]
+    }
+}
+
+/// Expression giving the next value of the variable of a `.for` from its step: `i++`, `i--`,
+/// `i += n`, `i -= n` or `i = expression`.
+pub(crate) fn next_value(step: &[Token], variable: &str) -> Option<Vec<Token>> {
+    let (name, rest) = step.split_first()?;
+    if name.kind != TokenKind::Identifier || name.text != variable {
+        return None;
+    }
+    match rest {
+        [first, second]
+            if first.text == second.text && (first.is_symbol("+") || first.is_symbol("-")) =>
+        {
+            Some(vec![
+                name.clone(),
+                first.clone(),
+                Token::new(TokenKind::Number, "1".to_string(), second.column),
+            ])
+        }
+        [operator, equals, value @ ..]
+            if (operator.is_symbol("+") || operator.is_symbol("-"))
+                && equals.is_symbol("=")
+                && !value.is_empty() =>
+        {
+            let mut next = vec![
+                name.clone(),
+                operator.clone(),
+                Token::new(TokenKind::Symbol, "(".to_string(), equals.column),
+            ];
+            next.extend_from_slice(value);
+            next.push(Token::new(
+                TokenKind::Symbol,
+                ")".to_string(),
+                equals.column,
+            ));
+            Some(next)
+        }
+        [equals, value @ ..] if equals.is_symbol("=") && !value.is_empty() => Some(value.to_vec()),
+        _ => None,
+    }
+}
+
+/// Name of the directive starting `line`, possibly after a label, without parsing the rest.
+///
+/// Lines of a macro body are only parsed once expanded, this finds where the body ends.
+pub(crate) fn block_directive(line: &Line) -> Option<&str> {
+    line.tokens
+        .iter()
+        .take(2)
+        .find(|token| token.kind == TokenKind::Identifier && token.text.starts_with('.'))
+        .map(|token| token.text.as_str())
+}
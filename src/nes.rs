@@ -0,0 +1,35 @@
+//! Hardware registers of the NES, which can be predefined as symbols.
+
+/// Memory-mapped registers of the PPU, the APU and the controllers, with their usual names.
+pub const REGISTERS: [(&str, i64); 30] = [
+    ("PPUCTRL", 0x2000),
+    ("PPUMASK", 0x2001),
+    ("PPUSTATUS", 0x2002),
+    ("OAMADDR", 0x2003),
+    ("OAMDATA", 0x2004),
+    ("PPUSCROLL", 0x2005),
+    ("PPUADDR", 0x2006),
+    ("PPUDATA", 0x2007),
+    ("SQ1_VOL", 0x4000),
+    ("SQ1_SWEEP", 0x4001),
+    ("SQ1_LO", 0x4002),
+    ("SQ1_HI", 0x4003),
+    ("SQ2_VOL", 0x4004),
+    ("SQ2_SWEEP", 0x4005),
+    ("SQ2_LO", 0x4006),
+    ("SQ2_HI", 0x4007),
+    ("TRI_LINEAR", 0x4008),
+    ("TRI_LO", 0x400a),
+    ("TRI_HI", 0x400b),
+    ("NOISE_VOL", 0x400c),
+    ("NOISE_LO", 0x400e),
+    ("NOISE_HI", 0x400f),
+    ("DMC_FREQ", 0x4010),
+    ("DMC_RAW", 0x4011),
+    ("DMC_START", 0x4012),
+    ("DMC_LEN", 0x4013),
+    ("OAMDMA", 0x4014),
+    ("SND_CHN", 0x4015),
+    ("JOY1", 0x4016),
+    ("JOY2", 0x4017),
+];
@@ -40,6 +40,35 @@ pub enum AddressingMode {
     ///
     /// The address $aa + Y + $01 contains the MSB of the EA.
     IndirectY,
+    /// Operand is a zero page address holding the EA, `($aa)` (65C02).
+    ZeroPageIndirect,
+    /// Operand is added to the index register X and the result holds the EA, `($aaaa,X)`
+    /// (65C02 `JMP`).
+    AbsoluteIndirectX,
+    /// A zero page address followed by a branch displacement, `$aa,target` (Rockwell `BBR`
+    /// and `BBS`).
+    ZeroPageRelative,
+    /// Operand is the 3 byte effective address, `$aaaaaa` (65816).
+    AbsoluteLong,
+    /// Operand is a 3 byte address added to the index register X, `$aaaaaa,X` (65816).
+    AbsoluteLongX,
+    /// Operand is a direct page address holding a 3 byte EA, `[$aa]` (65816).
+    DirectIndirectLong,
+    /// Operand is a direct page address holding a 3 byte address added to Y, `[$aa],Y`
+    /// (65816).
+    DirectIndirectLongY,
+    /// Operand is added to the stack pointer, `$aa,S` (65816).
+    StackRelative,
+    /// Operand is added to the stack pointer, the result holds an address added to Y,
+    /// `($aa,S),Y` (65816).
+    StackRelativeIndirectY,
+    /// Operand is an absolute address holding a 3 byte EA, `[$aaaa]` (65816 `JML`).
+    AbsoluteIndirectLong,
+    /// A 2 byte branch displacement (65816 `BRL` and `PER`).
+    RelativeLong,
+    /// Destination and source banks of a block move, written `src,dst` (65816 `MVN` and
+    /// `MVP`).
+    BlockMove,
 }
 
 #[derive(Clone)]
@@ -181,7 +210,7 @@ lazy_static! {
 
         /* Branching */
 
-        OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::Indirect), //AddressingMode that acts as Immidiate
+        OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::Absolute),
         OpCode::new(0x6c, "JMP", 3, 5, AddressingMode::Indirect), //AddressingMode:Indirect with 6502 bug
 
         OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
@@ -272,7 +301,7 @@ lazy_static! {
         OpCode::new(0xc7, "*DCP", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0xd7, "*DCP", 2, 6, AddressingMode::ZeroPageX),
         OpCode::new(0xCF, "*DCP", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xdF, "*DCP", 3, 7, AddressingMode::AbsoluteX),
+        OpCode::new(0xdf, "*DCP", 3, 7, AddressingMode::AbsoluteX),
         OpCode::new(0xdb, "*DCP", 3, 7, AddressingMode::AbsoluteY), // NOP CMP
         OpCode::new(0xd3, "*DCP", 2, 8, AddressingMode::IndirectY), // STP CMP
         OpCode::new(0xc3, "*DCP", 2, 8, AddressingMode::IndirectX), // NOP CMP
@@ -385,7 +414,6 @@ lazy_static! {
         OpCode::new(0x5a, "*NOP", 1,2, AddressingMode::Implied),
         OpCode::new(0x7a, "*NOP", 1,2, AddressingMode::Implied),
         OpCode::new(0xda, "*NOP", 1,2, AddressingMode::Implied),
-        OpCode::new(0xea, "NOP", 1,2, AddressingMode::Implied),
         OpCode::new(0xfa, "*NOP", 1,2, AddressingMode::Implied),
 
 
@@ -413,6 +441,43 @@ lazy_static! {
         OpCode::new(0x8f, "*SAX", 3, 4, AddressingMode::Absolute),
         OpCode::new(0x83, "*SAX", 2, 6, AddressingMode::IndirectX), // NOP STA
     ];
+    /// Instructions added by the CMOS 65C02 to the documented 6502 ones.
+    pub static ref CMOS_OP_CODES: Vec<OpCode> = vec![
+        OpCode::new(0x80, "BRA", 2, 3, AddressingMode::Relative),
+
+        OpCode::new(0xda, "PHX", 1, 3, AddressingMode::Implied),
+        OpCode::new(0x5a, "PHY", 1, 3, AddressingMode::Implied),
+        OpCode::new(0xfa, "PLX", 1, 4, AddressingMode::Implied),
+        OpCode::new(0x7a, "PLY", 1, 4, AddressingMode::Implied),
+
+        OpCode::new(0x64, "STZ", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x74, "STZ", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0x9c, "STZ", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x9e, "STZ", 3, 5, AddressingMode::AbsoluteX),
+
+        OpCode::new(0x14, "TRB", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x1c, "TRB", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x04, "TSB", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x0c, "TSB", 3, 6, AddressingMode::Absolute),
+
+        OpCode::new(0x1a, "INC", 1, 2, AddressingMode::Accumulator),
+        OpCode::new(0x3a, "DEC", 1, 2, AddressingMode::Accumulator),
+
+        OpCode::new(0x89, "BIT", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x34, "BIT", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0x3c, "BIT", 3, 4, AddressingMode::AbsoluteX),
+
+        OpCode::new(0x12, "ORA", 2, 5, AddressingMode::ZeroPageIndirect),
+        OpCode::new(0x32, "AND", 2, 5, AddressingMode::ZeroPageIndirect),
+        OpCode::new(0x52, "EOR", 2, 5, AddressingMode::ZeroPageIndirect),
+        OpCode::new(0x72, "ADC", 2, 5, AddressingMode::ZeroPageIndirect),
+        OpCode::new(0x92, "STA", 2, 5, AddressingMode::ZeroPageIndirect),
+        OpCode::new(0xb2, "LDA", 2, 5, AddressingMode::ZeroPageIndirect),
+        OpCode::new(0xd2, "CMP", 2, 5, AddressingMode::ZeroPageIndirect),
+        OpCode::new(0xf2, "SBC", 2, 5, AddressingMode::ZeroPageIndirect),
+
+        OpCode::new(0x7c, "JMP", 3, 6, AddressingMode::AbsoluteIndirectX),
+    ];
     pub static ref OPCODES_OP_MAP: HashMap<u8, &'static OpCode> = {
         let mut map: HashMap<u8, &'static OpCode> = HashMap::new();
         for cpuop in &*CPU_OP_CODES {
@@ -431,4 +496,502 @@ lazy_static! {
         }
         map
     };
+    /// Bit instructions added by the Rockwell R65C02 and the WDC W65C02S.
+    pub static ref ROCKWELL_OP_CODES: Vec<OpCode> = vec![
+        OpCode::new(0x07, "RMB0", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x17, "RMB1", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x27, "RMB2", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x37, "RMB3", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x47, "RMB4", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x57, "RMB5", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x67, "RMB6", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x77, "RMB7", 2, 5, AddressingMode::ZeroPage),
+
+        OpCode::new(0x87, "SMB0", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x97, "SMB1", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xa7, "SMB2", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xb7, "SMB3", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xc7, "SMB4", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xd7, "SMB5", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xe7, "SMB6", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xf7, "SMB7", 2, 5, AddressingMode::ZeroPage),
+
+        OpCode::new(0x0f, "BBR0", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0x1f, "BBR1", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0x2f, "BBR2", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0x3f, "BBR3", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0x4f, "BBR4", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0x5f, "BBR5", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0x6f, "BBR6", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0x7f, "BBR7", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+
+        OpCode::new(0x8f, "BBS0", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0x9f, "BBS1", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0xaf, "BBS2", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0xbf, "BBS3", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0xcf, "BBS4", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0xdf, "BBS5", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0xef, "BBS6", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+        OpCode::new(0xff, "BBS7", 3, 5/*+1 if branch taken*/, AddressingMode::ZeroPageRelative),
+    ];
+    /// Low power instructions added by the WDC W65C02S.
+    pub static ref WDC_OP_CODES: Vec<OpCode> = vec![
+        OpCode::new(0xcb, "WAI", 1, 3, AddressingMode::Implied),
+        OpCode::new(0xdb, "STP", 1, 3, AddressingMode::Implied),
+    ];
+    /// Instructions added by the WDC 65816 to the W65C02S, except for the bit instructions
+    /// whose opcodes it reuses.
+    pub static ref WDC_65816_OP_CODES: Vec<OpCode> = vec![
+        OpCode::new(0x03, "ORA", 2, 4, AddressingMode::StackRelative),
+        OpCode::new(0x07, "ORA", 2, 6, AddressingMode::DirectIndirectLong),
+        OpCode::new(0x0f, "ORA", 4, 5, AddressingMode::AbsoluteLong),
+        OpCode::new(0x13, "ORA", 2, 7, AddressingMode::StackRelativeIndirectY),
+        OpCode::new(0x17, "ORA", 2, 6, AddressingMode::DirectIndirectLongY),
+        OpCode::new(0x1f, "ORA", 4, 5, AddressingMode::AbsoluteLongX),
+
+        OpCode::new(0x23, "AND", 2, 4, AddressingMode::StackRelative),
+        OpCode::new(0x27, "AND", 2, 6, AddressingMode::DirectIndirectLong),
+        OpCode::new(0x2f, "AND", 4, 5, AddressingMode::AbsoluteLong),
+        OpCode::new(0x33, "AND", 2, 7, AddressingMode::StackRelativeIndirectY),
+        OpCode::new(0x37, "AND", 2, 6, AddressingMode::DirectIndirectLongY),
+        OpCode::new(0x3f, "AND", 4, 5, AddressingMode::AbsoluteLongX),
+
+        OpCode::new(0x43, "EOR", 2, 4, AddressingMode::StackRelative),
+        OpCode::new(0x47, "EOR", 2, 6, AddressingMode::DirectIndirectLong),
+        OpCode::new(0x4f, "EOR", 4, 5, AddressingMode::AbsoluteLong),
+        OpCode::new(0x53, "EOR", 2, 7, AddressingMode::StackRelativeIndirectY),
+        OpCode::new(0x57, "EOR", 2, 6, AddressingMode::DirectIndirectLongY),
+        OpCode::new(0x5f, "EOR", 4, 5, AddressingMode::AbsoluteLongX),
+
+        OpCode::new(0x63, "ADC", 2, 4, AddressingMode::StackRelative),
+        OpCode::new(0x67, "ADC", 2, 6, AddressingMode::DirectIndirectLong),
+        OpCode::new(0x6f, "ADC", 4, 5, AddressingMode::AbsoluteLong),
+        OpCode::new(0x73, "ADC", 2, 7, AddressingMode::StackRelativeIndirectY),
+        OpCode::new(0x77, "ADC", 2, 6, AddressingMode::DirectIndirectLongY),
+        OpCode::new(0x7f, "ADC", 4, 5, AddressingMode::AbsoluteLongX),
+
+        OpCode::new(0x83, "STA", 2, 4, AddressingMode::StackRelative),
+        OpCode::new(0x87, "STA", 2, 6, AddressingMode::DirectIndirectLong),
+        OpCode::new(0x8f, "STA", 4, 5, AddressingMode::AbsoluteLong),
+        OpCode::new(0x93, "STA", 2, 7, AddressingMode::StackRelativeIndirectY),
+        OpCode::new(0x97, "STA", 2, 6, AddressingMode::DirectIndirectLongY),
+        OpCode::new(0x9f, "STA", 4, 5, AddressingMode::AbsoluteLongX),
+
+        OpCode::new(0xa3, "LDA", 2, 4, AddressingMode::StackRelative),
+        OpCode::new(0xa7, "LDA", 2, 6, AddressingMode::DirectIndirectLong),
+        OpCode::new(0xaf, "LDA", 4, 5, AddressingMode::AbsoluteLong),
+        OpCode::new(0xb3, "LDA", 2, 7, AddressingMode::StackRelativeIndirectY),
+        OpCode::new(0xb7, "LDA", 2, 6, AddressingMode::DirectIndirectLongY),
+        OpCode::new(0xbf, "LDA", 4, 5, AddressingMode::AbsoluteLongX),
+
+        OpCode::new(0xc3, "CMP", 2, 4, AddressingMode::StackRelative),
+        OpCode::new(0xc7, "CMP", 2, 6, AddressingMode::DirectIndirectLong),
+        OpCode::new(0xcf, "CMP", 4, 5, AddressingMode::AbsoluteLong),
+        OpCode::new(0xd3, "CMP", 2, 7, AddressingMode::StackRelativeIndirectY),
+        OpCode::new(0xd7, "CMP", 2, 6, AddressingMode::DirectIndirectLongY),
+        OpCode::new(0xdf, "CMP", 4, 5, AddressingMode::AbsoluteLongX),
+
+        OpCode::new(0xe3, "SBC", 2, 4, AddressingMode::StackRelative),
+        OpCode::new(0xe7, "SBC", 2, 6, AddressingMode::DirectIndirectLong),
+        OpCode::new(0xef, "SBC", 4, 5, AddressingMode::AbsoluteLong),
+        OpCode::new(0xf3, "SBC", 2, 7, AddressingMode::StackRelativeIndirectY),
+        OpCode::new(0xf7, "SBC", 2, 6, AddressingMode::DirectIndirectLongY),
+        OpCode::new(0xff, "SBC", 4, 5, AddressingMode::AbsoluteLongX),
+
+        /* Branches, jumps and calls */
+        OpCode::new(0x82, "BRL", 3, 4, AddressingMode::RelativeLong),
+        OpCode::new(0x5c, "JML", 4, 4, AddressingMode::AbsoluteLong),
+        OpCode::new(0xdc, "JML", 3, 6, AddressingMode::AbsoluteIndirectLong),
+        OpCode::new(0x22, "JSL", 4, 8, AddressingMode::AbsoluteLong),
+        OpCode::new(0xfc, "JSR", 3, 8, AddressingMode::AbsoluteIndirectX),
+        OpCode::new(0x6b, "RTL", 1, 6, AddressingMode::Implied),
+        OpCode::new(0x02, "COP", 2, 7, AddressingMode::Immediate),
+        OpCode::new(0x42, "WDM", 2, 2, AddressingMode::Immediate),
+
+        /* Block moves */
+        OpCode::new(0x54, "MVN", 3, 7/*per byte moved*/, AddressingMode::BlockMove),
+        OpCode::new(0x44, "MVP", 3, 7/*per byte moved*/, AddressingMode::BlockMove),
+
+        /* Stack */
+        OpCode::new(0xf4, "PEA", 3, 5, AddressingMode::Absolute),
+        OpCode::new(0xd4, "PEI", 2, 6, AddressingMode::ZeroPageIndirect),
+        OpCode::new(0x62, "PER", 3, 6, AddressingMode::RelativeLong),
+        OpCode::new(0x8b, "PHB", 1, 3, AddressingMode::Implied),
+        OpCode::new(0x0b, "PHD", 1, 4, AddressingMode::Implied),
+        OpCode::new(0x4b, "PHK", 1, 3, AddressingMode::Implied),
+        OpCode::new(0xab, "PLB", 1, 4, AddressingMode::Implied),
+        OpCode::new(0x2b, "PLD", 1, 5, AddressingMode::Implied),
+
+        /* Status and transfers */
+        OpCode::new(0xc2, "REP", 2, 3, AddressingMode::Immediate),
+        OpCode::new(0xe2, "SEP", 2, 3, AddressingMode::Immediate),
+        OpCode::new(0x5b, "TCD", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x1b, "TCS", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x7b, "TDC", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x3b, "TSC", 1, 2, AddressingMode::Implied),
+        OpCode::new(0x9b, "TXY", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xbb, "TYX", 1, 2, AddressingMode::Implied),
+        OpCode::new(0xeb, "XBA", 1, 3, AddressingMode::Implied),
+        OpCode::new(0xfb, "XCE", 1, 2, AddressingMode::Implied),
+    ];
+    static ref CMOS_TABLE: OpCodeTable = OpCodeTable::new(documented().chain(CMOS_OP_CODES.iter()));
+    static ref ROCKWELL_TABLE: OpCodeTable = OpCodeTable::new(
+        documented()
+            .chain(CMOS_OP_CODES.iter())
+            .chain(ROCKWELL_OP_CODES.iter())
+    );
+    static ref WDC_TABLE: OpCodeTable = OpCodeTable::new(
+        documented()
+            .chain(CMOS_OP_CODES.iter())
+            .chain(ROCKWELL_OP_CODES.iter())
+            .chain(WDC_OP_CODES.iter())
+    );
+    static ref WDC_65816_TABLE: OpCodeTable = OpCodeTable::new(
+        documented()
+            .chain(CMOS_OP_CODES.iter())
+            .chain(WDC_OP_CODES.iter())
+            .chain(WDC_65816_OP_CODES.iter())
+    );
+}
+
+/// Instructions of a CMOS processor, which has no undocumented opcodes.
+struct OpCodeTable {
+    by_code: HashMap<u8, &'static OpCode>,
+    by_mnemonic: HashMap<&'static str, Vec<&'static OpCode>>,
+}
+
+impl OpCodeTable {
+    fn new(opcodes: impl Iterator<Item = &'static OpCode>) -> Self {
+        let mut table = OpCodeTable {
+            by_code: HashMap::new(),
+            by_mnemonic: HashMap::new(),
+        };
+        for opcode in opcodes {
+            table.by_code.insert(opcode.code, opcode);
+            table
+                .by_mnemonic
+                .entry(opcode.mnemonic)
+                .or_default()
+                .push(opcode);
+        }
+        table
+    }
+}
+
+/// Documented instructions of the NMOS 6502.
+fn documented() -> impl Iterator<Item = &'static OpCode> {
+    CPU_OP_CODES
+        .iter()
+        .filter(|opcode| !opcode.mnemonic.starts_with('*'))
+}
+
+/// Common names of undocumented opcodes that differ from the names in `CPU_OP_CODES`.
+const ILLEGAL_ALIASES: [(&str, &str); 12] = [
+    ("ISC", "ISB"),
+    ("INS", "ISB"),
+    ("DCM", "DCP"),
+    ("SBX", "AXS"),
+    ("ASO", "SLO"),
+    ("LSE", "SRE"),
+    ("ASR", "ALR"),
+    ("AXA", "AHX"),
+    ("SHA", "AHX"),
+    ("LAR", "LAS"),
+    ("SHS", "TAS"),
+    ("ANE", "XAA"),
+];
+
+/// Name of an undocumented opcode in `CPU_OP_CODES`, given its name or an alias.
+fn illegal_name(mnemonic: &str) -> String {
+    let name = ILLEGAL_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == mnemonic)
+        .map_or(mnemonic, |(_, name)| name);
+    format!("*{}", name)
+}
+
+/// Check whether `mnemonic` names an instruction of any CPU configuration.
+pub fn is_mnemonic(mnemonic: &str) -> bool {
+    OPCODES_MAP.contains_key(mnemonic)
+        || OPCODES_MAP.contains_key(illegal_name(mnemonic).as_str())
+        || WDC_TABLE.by_mnemonic.contains_key(mnemonic)
+        || WDC_65816_TABLE.by_mnemonic.contains_key(mnemonic)
+}
+
+/// Check whether `mnemonic` names an undocumented NMOS opcode, with or without its `*` prefix.
+pub fn is_illegal(mnemonic: &str) -> bool {
+    let name = mnemonic.strip_prefix('*').unwrap_or(mnemonic);
+    OPCODES_MAP.contains_key(illegal_name(name).as_str())
+}
+
+/// Processor the code is written for.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CpuModel {
+    /// The original NMOS 6502, with its undocumented opcodes.
+    #[default]
+    Mos6502,
+    /// The CMOS 65C02, which adds instructions and addressing modes but has no undocumented
+    /// opcodes.
+    Cmos65C02,
+    /// The Rockwell R65C02, which adds the `RMB`, `SMB`, `BBR` and `BBS` bit instructions.
+    Rockwell65C02,
+    /// The WDC W65C02S, which adds `WAI` and `STP` to the Rockwell instructions.
+    Wdc65C02,
+    /// The WDC 65816, with 24-bit addresses and 16-bit registers.
+    Wdc65816,
+    /// The Ricoh 2A03 of the NES, an NMOS 6502 without decimal mode.
+    Ricoh2A03,
+}
+
+impl CpuModel {
+    pub const ALL: [CpuModel; 6] = [
+        CpuModel::Mos6502,
+        CpuModel::Cmos65C02,
+        CpuModel::Rockwell65C02,
+        CpuModel::Wdc65C02,
+        CpuModel::Wdc65816,
+        CpuModel::Ricoh2A03,
+    ];
+    /// Name used to select the model, such as `65c02`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuModel::Mos6502 => "6502",
+            CpuModel::Cmos65C02 => "65c02",
+            CpuModel::Rockwell65C02 => "r65c02",
+            CpuModel::Wdc65C02 => "w65c02",
+            CpuModel::Wdc65816 => "65816",
+            CpuModel::Ricoh2A03 => "2a03",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        CpuModel::ALL
+            .iter()
+            .copied()
+            .find(|model| model.name().eq_ignore_ascii_case(name))
+    }
+    /// Number of addressable bytes.
+    pub fn address_space(&self) -> u32 {
+        match self {
+            CpuModel::Wdc65816 => 0x100_0000,
+            _ => 0x10000,
+        }
+    }
+    /// Check whether this is an NMOS 6502, with its undocumented opcodes and page wrap bugs.
+    pub fn is_nmos(&self) -> bool {
+        self.table().is_none()
+    }
+    /// Instructions of a CMOS model, or `None` for the NMOS 6502.
+    fn table(&self) -> Option<&'static OpCodeTable> {
+        match self {
+            CpuModel::Mos6502 | CpuModel::Ricoh2A03 => None,
+            CpuModel::Cmos65C02 => Some(&CMOS_TABLE),
+            CpuModel::Rockwell65C02 => Some(&ROCKWELL_TABLE),
+            CpuModel::Wdc65C02 => Some(&WDC_TABLE),
+            CpuModel::Wdc65816 => Some(&WDC_65816_TABLE),
+        }
+    }
+}
+
+/// Which instructions the assembler and disassembler accept.
+#[derive(Clone, Debug, Default)]
+pub struct CpuOptions {
+    pub model: CpuModel,
+    /// Accept the undocumented NMOS opcodes under their usual names, without a `*` prefix.
+    pub allow_illegal: bool,
+    /// Assume a 16-bit accumulator on the 65816, as set by `.a16`, for the width of immediates.
+    pub wide_accumulator: bool,
+    /// Assume 16-bit index registers on the 65816, as set by `.i16`.
+    pub wide_index: bool,
+}
+
+impl CpuOptions {
+    pub fn new(model: CpuModel) -> Self {
+        CpuOptions {
+            model,
+            ..Default::default()
+        }
+    }
+    /// Opcodes of `mnemonic` as written in source, documented ones first.
+    ///
+    /// Undocumented NMOS opcodes can always be written with a `*` prefix on the 6502, and not
+    /// at all on CPUs without them.
+    pub fn opcodes(&self, mnemonic: &str) -> Vec<&'static OpCode> {
+        if let Some(table) = self.model.table() {
+            return table.by_mnemonic.get(mnemonic).cloned().unwrap_or_default();
+        }
+        let mut result = OPCODES_MAP.get(mnemonic).cloned().unwrap_or_default();
+        if self.allow_illegal && !mnemonic.starts_with('*') {
+            if let Some(opcodes) = OPCODES_MAP.get(illegal_name(mnemonic).as_str()) {
+                result.extend(opcodes);
+            }
+        }
+        result
+    }
+    /// Number of operand bytes of `opcode`, which for 65816 immediates depends on the width of
+    /// the register they are loaded into.
+    pub fn operand_len(&self, opcode: &OpCode) -> usize {
+        let wide = self.model == CpuModel::Wdc65816
+            && opcode.mode == AddressingMode::Immediate
+            && match opcode.mnemonic {
+                "ADC" | "AND" | "BIT" | "CMP" | "EOR" | "LDA" | "ORA" | "SBC" => {
+                    self.wide_accumulator
+                }
+                "CPX" | "CPY" | "LDX" | "LDY" => self.wide_index,
+                _ => false,
+            };
+        opcode.len as usize - 1 + wide as usize
+    }
+    /// Opcode of `code` for the disassembler, if this CPU accepts it.
+    pub fn decode(&self, code: u8) -> Option<&'static OpCode> {
+        if let Some(table) = self.model.table() {
+            return table.by_code.get(&code).copied();
+        }
+        OPCODES_OP_MAP
+            .get(&code)
+            .copied()
+            .filter(|opcode| self.allow_illegal || !opcode.mnemonic.starts_with('*'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asm6502, DecompileOptions};
+
+    /// Operand written the way the disassembler prints it, for an instruction at $8000.
+    fn operand(mode: &AddressingMode) -> &'static str {
+        match mode {
+            AddressingMode::NoneAddressing
+            | AddressingMode::Implied
+            | AddressingMode::Accumulator => "",
+            AddressingMode::Relative => " $8010",
+            AddressingMode::Immediate => " #$12",
+            AddressingMode::ZeroPage => " $12",
+            AddressingMode::ZeroPageX => " $12,X",
+            AddressingMode::ZeroPageY => " $12,Y",
+            AddressingMode::Absolute => " $1234",
+            AddressingMode::AbsoluteX => " $1234,X",
+            AddressingMode::AbsoluteY => " $1234,Y",
+            AddressingMode::Indirect => " ($1234)",
+            AddressingMode::IndirectX => " ($12,X)",
+            AddressingMode::IndirectY => " ($12),Y",
+            AddressingMode::ZeroPageIndirect => " ($12)",
+            AddressingMode::AbsoluteIndirectX => " ($1234,X)",
+            AddressingMode::ZeroPageRelative => " $12,$8010",
+            AddressingMode::AbsoluteLong => " $123456",
+            AddressingMode::AbsoluteLongX => " $123456,X",
+            AddressingMode::DirectIndirectLong => " [$12]",
+            AddressingMode::DirectIndirectLongY => " [$12],Y",
+            AddressingMode::StackRelative => " $12,S",
+            AddressingMode::StackRelativeIndirectY => " ($12,S),Y",
+            AddressingMode::AbsoluteIndirectLong => " [$1234]",
+            AddressingMode::RelativeLong => " $8010",
+            AddressingMode::BlockMove => " $01,$02",
+        }
+    }
+
+    #[test]
+    fn length_matches_mode() {
+        let opcodes = CPU_OP_CODES
+            .iter()
+            .chain(CMOS_OP_CODES.iter())
+            .chain(ROCKWELL_OP_CODES.iter())
+            .chain(WDC_OP_CODES.iter())
+            .chain(WDC_65816_OP_CODES.iter());
+        for opcode in opcodes {
+            let len = match opcode.mode {
+                AddressingMode::NoneAddressing
+                | AddressingMode::Implied
+                | AddressingMode::Accumulator => 1,
+                AddressingMode::Absolute
+                | AddressingMode::AbsoluteX
+                | AddressingMode::AbsoluteY
+                | AddressingMode::Indirect
+                | AddressingMode::AbsoluteIndirectX
+                | AddressingMode::ZeroPageRelative
+                | AddressingMode::AbsoluteIndirectLong
+                | AddressingMode::RelativeLong
+                | AddressingMode::BlockMove => 3,
+                AddressingMode::AbsoluteLong | AddressingMode::AbsoluteLongX => 4,
+                _ => 2,
+            };
+            assert_eq!(opcode.len, len, "length of {:02X}", opcode.code);
+        }
+    }
+
+    #[test]
+    fn unique_codes_and_modes() {
+        assert_eq!(OPCODES_OP_MAP.len(), CPU_OP_CODES.len());
+        assert_eq!(
+            WDC_TABLE.by_code.len(),
+            documented().count()
+                + CMOS_OP_CODES.len()
+                + ROCKWELL_OP_CODES.len()
+                + WDC_OP_CODES.len()
+        );
+        assert_eq!(
+            WDC_65816_TABLE.by_code.len(),
+            documented().count()
+                + CMOS_OP_CODES.len()
+                + WDC_OP_CODES.len()
+                + WDC_65816_OP_CODES.len()
+        );
+        let tables = OPCODES_MAP
+            .iter()
+            .chain(WDC_TABLE.by_mnemonic.iter())
+            .chain(WDC_65816_TABLE.by_mnemonic.iter());
+        for (mnemonic, opcodes) in tables {
+            // Several undocumented opcodes behave the same, only documented ones must be unique.
+            if mnemonic.starts_with('*') {
+                continue;
+            }
+            for (i, opcode) in opcodes.iter().enumerate() {
+                assert!(
+                    opcodes[i + 1..]
+                        .iter()
+                        .all(|other| other.mode != opcode.mode),
+                    "{} has several {:?} opcodes",
+                    mnemonic,
+                    opcode.mode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn assemble_disassemble_round_trip() {
+        let wdc = CpuOptions::new(CpuModel::Wdc65C02);
+        let cmos = CMOS_OP_CODES
+            .iter()
+            .chain(ROCKWELL_OP_CODES.iter())
+            .chain(WDC_OP_CODES.iter());
+        let cases = documented()
+            .map(|opcode| (CpuOptions::default(), opcode))
+            .chain(cmos.map(|opcode| (wdc.clone(), opcode)))
+            .chain(
+                WDC_65816_OP_CODES
+                    .iter()
+                    .map(|opcode| (CpuOptions::new(CpuModel::Wdc65816), opcode)),
+            );
+        for (cpu, opcode) in cases {
+            let source = format!("{}{}", opcode.mnemonic, operand(&opcode.mode));
+            let mut asm = Asm6502::new(source.clone(), 0x8000);
+            asm.cpu = cpu.clone();
+            if let Err(errors) = asm.compile() {
+                panic!("{}: {}", source, errors[0]);
+            }
+            let bytes = asm.to_binary(0);
+            assert_eq!(bytes[0], opcode.code, "{}", source);
+            assert_eq!(bytes.len(), opcode.len as usize, "{}", source);
+            let options = DecompileOptions {
+                cpu,
+                ..Default::default()
+            };
+            // `REP` and `SEP` are followed by the register widths they set.
+            assert_eq!(
+                Asm6502::decompile_with(bytes, 0x8000, &options)[0],
+                format!("8000\t{}", source)
+            );
+        }
+    }
 }
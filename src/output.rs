@@ -0,0 +1,50 @@
+use crate::{InstructionAddress, Item};
+
+/// A contiguous run of assembled bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub start: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Segment {
+    /// Address following the last byte of the segment.
+    pub fn end(&self) -> u32 {
+        self.start + self.bytes.len() as u32
+    }
+}
+
+/// Group compiled items into segments of contiguous memory, sorted by address.
+pub fn segments(items: &[Item]) -> Vec<Segment> {
+    let mut result: Vec<Segment> = vec![];
+    for item in items {
+        let (address, mut bytes) = item.clone().into();
+        let start = match address {
+            InstructionAddress::Address(start) => start,
+            _ => panic!("compiled items always have an address"),
+        };
+        if bytes.is_empty() {
+            continue;
+        }
+        match result.last_mut() {
+            Some(segment) if segment.end() == start => segment.bytes.append(&mut bytes),
+            _ => result.push(Segment { start, bytes }),
+        }
+    }
+    result.sort_by_key(|segment| segment.start);
+    result
+}
+
+/// Lay segments out as a single image starting at the lowest address, filling the gaps
+/// between them with `pad`.
+pub fn to_binary(segments: &[Segment], pad: u8) -> Vec<u8> {
+    let mut result = vec![];
+    if let Some(first) = segments.first() {
+        let base = first.start as usize;
+        for segment in segments {
+            result.resize(segment.start as usize - base, pad);
+            result.extend_from_slice(&segment.bytes);
+        }
+    }
+    result
+}
@@ -0,0 +1,221 @@
+use crate::error::{CompileError, ErrorKind};
+use crate::lexer::{Line, Token, TokenKind};
+use crate::ops;
+use crate::{InstructionAddress, Result};
+
+/// A source line split into its label or address, mnemonic and operand.
+#[derive(Clone, Debug)]
+pub struct Statement {
+    pub line: usize,
+    pub address: InstructionAddress,
+    /// Mnemonic of an instruction or name of a directive, starting with `.`.
+    pub mnemonic: Option<Token>,
+    /// Tokens following the mnemonic.
+    pub operand: Vec<Token>,
+}
+
+/// Check whether `token` names an instruction, in any case like the registers.
+fn is_mnemonic(token: &Token) -> bool {
+    token.kind == TokenKind::Identifier && ops::is_mnemonic(&token.text.to_ascii_uppercase())
+}
+
+/// Name of the instruction `token` in the upper case of the opcode tables, or its text if it
+/// names something else, such as a directive or a macro.
+fn mnemonic_name(token: &Token) -> String {
+    if is_mnemonic(token) {
+        token.text.to_ascii_uppercase()
+    } else {
+        token.text.clone()
+    }
+}
+
+/// Width of an operand forced with a suffix on the mnemonic, `LDA.abs`, or with a prefix on
+/// the operand, `LDA a:value`, rather than chosen from its value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Width {
+    /// `.zp` or `z:`
+    ZeroPage,
+    /// `.abs` or `a:`
+    Absolute,
+    /// `.long` or `f:`, for the 24-bit addresses of the 65816.
+    Long,
+}
+
+impl Width {
+    const ALL: [Width; 3] = [Width::ZeroPage, Width::Absolute, Width::Long];
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Width::ZeroPage => ".zp",
+            Width::Absolute => ".abs",
+            Width::Long => ".long",
+        }
+    }
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Width::ZeroPage => "z",
+            Width::Absolute => "a",
+            Width::Long => "f",
+        }
+    }
+    fn from_prefix(prefix: &str) -> Option<Self> {
+        Width::ALL
+            .iter()
+            .find(|width| width.prefix().eq_ignore_ascii_case(prefix))
+            .cloned()
+    }
+    /// Split a width suffix off `mnemonic`.
+    pub fn split(mnemonic: &str) -> (&str, Option<Self>) {
+        for width in Width::ALL {
+            let at = mnemonic.len().saturating_sub(width.suffix().len());
+            if mnemonic.is_char_boundary(at) && mnemonic[at..].eq_ignore_ascii_case(width.suffix())
+            {
+                return (&mnemonic[..at], Some(width));
+            }
+        }
+        (mnemonic, None)
+    }
+}
+
+/// Check whether `token`, starting a line followed by an instruction, is the address of the
+/// instruction rather than a label.
+pub(crate) fn is_address(token: &Token, tabbed: bool) -> bool {
+    let digits = token.text.chars().all(|c| c.is_ascii_hexdigit());
+    // A word made of hex letters, as `beef` or `fade`, is a label unless a tab follows it.
+    let numeric = token.kind == TokenKind::Number || tabbed;
+    // Six digits give a 24-bit address, as long as they can't be read as a word like `DECADE`.
+    digits
+        && numeric
+        && (token.text.len() <= 4
+            || token.text.len() == 6 && token.text.chars().any(|c| c.is_ascii_digit()))
+}
+
+impl Statement {
+    /// Split a line of tokens into its fields.
+    ///
+    /// A label is either followed by `:` or starts at the first column, in which case a hex
+    /// number followed by an instruction is taken as the address of that instruction, if it
+    /// starts with a digit or a tab follows it.
+    /// Directives may start at the first column too, and may be preceded by an indented label.
+    ///
+    /// `NAME = value` is read as `NAME .equ value`.
+    pub fn parse(line: &Line) -> Result<Self> {
+        let tokens = &line.tokens[..];
+        let mut address = InstructionAddress::None;
+        let mut rest = tokens;
+        if tokens.len() > 1 && tokens[0].kind == TokenKind::Identifier && tokens[1].is_symbol("=") {
+            return Ok(Statement {
+                line: line.number,
+                address: InstructionAddress::Label(tokens[0].text.clone()),
+                mnemonic: Some(Token::new(
+                    TokenKind::Identifier,
+                    ".equ".to_string(),
+                    tokens[1].column,
+                )),
+                operand: tokens[2..].to_vec(),
+            });
+        }
+        if tokens.len() > 1 && tokens[0].kind == TokenKind::Identifier && tokens[1].is_symbol(":") {
+            address = InstructionAddress::Label(tokens[0].text.clone());
+            rest = &tokens[2..];
+        } else if tokens.len() > 1
+            && tokens[0].column > 1
+            && tokens[0].kind == TokenKind::Identifier
+            && !is_mnemonic(&tokens[0])
+            && !tokens[0].text.starts_with('.')
+            && tokens[1].kind == TokenKind::Identifier
+            && tokens[1].text.starts_with('.')
+        {
+            address = InstructionAddress::Label(tokens[0].text.clone());
+            rest = &tokens[1..];
+        } else if tokens[0].column == 1
+            && !is_mnemonic(&tokens[0])
+            && !tokens[0].text.starts_with('.')
+        {
+            let first = &tokens[0];
+            if tokens.len() > 1 && first.kind != TokenKind::Symbol && is_address(first, line.tabbed)
+            {
+                // Validated above, so this cannot fail.
+                address =
+                    InstructionAddress::Address(u32::from_str_radix(&first.text, 16).unwrap());
+                rest = &tokens[1..];
+            } else if first.kind == TokenKind::Identifier {
+                address = InstructionAddress::Label(first.text.clone());
+                rest = &tokens[1..];
+            }
+        }
+        let (mnemonic, operand) = match rest {
+            [] => (None, vec![]),
+            [star, equals, operand @ ..] if star.is_symbol("*") && equals.is_symbol("=") => {
+                // `*=` is a synonym for `.org`.
+                (
+                    Some(Token::new(
+                        TokenKind::Identifier,
+                        ".org".to_string(),
+                        star.column,
+                    )),
+                    operand.to_vec(),
+                )
+            }
+            [star, name, operand @ ..]
+                if star.is_symbol("*")
+                    && name.kind == TokenKind::Identifier
+                    && name.column == star.column + 1 =>
+            {
+                // Undocumented opcodes are written with a `*` prefix.
+                let text = format!("*{}", name.text.to_ascii_uppercase());
+                (
+                    Some(Token::new(TokenKind::Identifier, text, star.column)),
+                    operand.to_vec(),
+                )
+            }
+            [name, suffix, operand @ ..]
+                if is_mnemonic(name)
+                    && suffix.kind == TokenKind::Identifier
+                    && suffix.text.starts_with('.')
+                    && suffix.column == name.column + name.text.len() =>
+            {
+                // A width suffix stays on the mnemonic, `LDA.abs`.
+                let text = format!("{}{}", mnemonic_name(name), suffix.text);
+                (
+                    Some(Token::new(TokenKind::Identifier, text, name.column)),
+                    operand.to_vec(),
+                )
+            }
+            [name, prefix, colon, operand @ ..]
+                if is_mnemonic(name)
+                    && prefix.kind == TokenKind::Identifier
+                    && colon.is_symbol(":")
+                    && Width::from_prefix(&prefix.text).is_some() =>
+            {
+                // A width prefix on the operand, `LDA a:value`, is read as the matching suffix.
+                let width = Width::from_prefix(&prefix.text).unwrap();
+                let text = format!("{}{}", mnemonic_name(name), width.suffix());
+                (
+                    Some(Token::new(TokenKind::Identifier, text, name.column)),
+                    operand.to_vec(),
+                )
+            }
+            [mnemonic, operand @ ..] if mnemonic.kind == TokenKind::Identifier => (
+                Some(Token {
+                    text: mnemonic_name(mnemonic),
+                    ..mnemonic.clone()
+                }),
+                operand.to_vec(),
+            ),
+            [unexpected, ..] => {
+                return Err(CompileError::at(
+                    ErrorKind::SyntaxError,
+                    line.number,
+                    unexpected.column,
+                    "expected a mnemonic",
+                ))
+            }
+        };
+        Ok(Statement {
+            line: line.number,
+            address,
+            mnemonic,
+            operand,
+        })
+    }
+}
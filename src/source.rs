@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{CompileError, Note};
+use crate::lexer::{self, Line};
+
+/// A source file: the main input or a file read by `.include`.
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+    /// Name used in errors.
+    pub name: Option<String>,
+    /// Where the file was read from, which the files it includes are looked for next to.
+    pub path: Option<PathBuf>,
+    pub text: String,
+    /// Line of the `.include` that read the file.
+    pub included_from: Option<usize>,
+    /// Number of lines in the files read before this one.
+    offset: usize,
+}
+
+/// Files of a compilation.
+///
+/// The assembler numbers lines across every file, in the order the files were read, and
+/// errors are mapped back to a file and a line within it once the compilation is done.
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    files: Vec<SourceFile>,
+    lines: usize,
+}
+
+/// Path of `path` with the links and `..` resolved, to tell whether two paths are one file.
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Sources::default()
+    }
+    /// Add a file and split it into lines, numbered after the lines of the files added before.
+    pub fn add(
+        &mut self,
+        name: Option<String>,
+        path: Option<PathBuf>,
+        text: String,
+        included_from: Option<usize>,
+    ) -> (Vec<Line>, Vec<CompileError>) {
+        let offset = self.lines;
+        let (mut lines, mut errors) = lexer::tokenize(&text);
+        for line in lines.iter_mut() {
+            line.number += offset;
+        }
+        for err in errors.iter_mut() {
+            err.line += offset;
+        }
+        self.lines += text.lines().count();
+        self.files.push(SourceFile {
+            name,
+            path,
+            text,
+            included_from,
+            offset,
+        });
+        (lines, errors)
+    }
+    /// File containing `line`, with the number of the line within that file.
+    pub fn locate(&self, line: usize) -> Option<(&SourceFile, usize)> {
+        self.files
+            .iter()
+            .rev()
+            .find(|file| file.offset < line)
+            .map(|file| (file, line - file.offset))
+    }
+    /// Text of `line`.
+    pub fn text(&self, line: usize) -> Option<String> {
+        let (file, line) = self.locate(line)?;
+        file.text.lines().nth(line - 1).map(|text| text.to_string())
+    }
+    /// Find a file named by an `.include` or `.incbin` on `line`, next to the file containing
+    /// that line first, then in each of `search_paths`.
+    pub fn resolve(&self, name: &str, line: usize, search_paths: &[PathBuf]) -> Option<PathBuf> {
+        let directory = self
+            .locate(line)
+            .and_then(|(file, _)| file.path.as_deref())
+            .and_then(|path| path.parent())
+            .unwrap_or_else(|| Path::new(""));
+        std::iter::once(directory)
+            .chain(search_paths.iter().map(|path| path.as_path()))
+            .map(|directory| directory.join(name))
+            .find(|path| path.is_file())
+    }
+    /// Check whether `path` is the file containing `line` or one of the files including it,
+    /// in which case including it again would never end.
+    pub fn is_including(&self, path: &Path, line: usize) -> bool {
+        let path = canonical(path);
+        let mut line = Some(line);
+        while let Some((file, _)) = line.and_then(|line| self.locate(line)) {
+            if file.path.as_deref().map(canonical).as_ref() == Some(&path) {
+                return true;
+            }
+            line = file.included_from;
+        }
+        false
+    }
+    /// Notes pointing at the `.include` lines that led to `line`, innermost first.
+    pub fn include_stack(&self, line: usize) -> Vec<Note> {
+        let mut notes = vec![];
+        let mut including = self.locate(line).and_then(|(file, _)| file.included_from);
+        while let Some((file, number)) = including.and_then(|line| self.locate(line)) {
+            notes.push(Note {
+                file: file.name.clone(),
+                source: self.text(including.unwrap()),
+                ..Note::new(number, "included from here")
+            });
+            including = file.included_from;
+        }
+        notes
+    }
+    /// Replace the line numbers of `err` and its notes by a file and a line within it,
+    /// adding the source text and the include stack of the error.
+    pub fn locate_error(&self, err: &mut CompileError) {
+        let stack = self.include_stack(err.line);
+        if let Some((file, number)) = self.locate(err.line) {
+            err.source = self.text(err.line);
+            err.file = err.file.take().or_else(|| file.name.clone());
+            err.line = number;
+        }
+        for note in err.notes.iter_mut() {
+            if let Some((file, number)) = self.locate(note.line) {
+                note.source = self.text(note.line);
+                note.file = note.file.take().or_else(|| file.name.clone());
+                note.line = number;
+            }
+        }
+        err.notes.extend(stack);
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymbolKind {
+    /// Address of an instruction or data, defined with `label:`.
+    Label,
+    /// Value defined with `NAME = value` or `.equ`.
+    Constant,
+    /// Value defined with `.set`, which may be assigned again.
+    Variable,
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub value: i64,
+    pub kind: SymbolKind,
+    /// Line the symbol was last defined on.
+    pub line: usize,
+}
+
+/// Symbols defined while compiling, by name qualified by the scopes they were defined in,
+/// as `draw::loop`.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    symbols: HashMap<String, Symbol>,
+    /// Scope that names are defined in and looked up from, empty at the top level.
+    pub scope: String,
+}
+
+/// Name of `name` inside `scope`.
+pub fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}::{}", scope, name)
+    }
+}
+
+/// Split a qualified name into its scope and its name within that scope.
+pub fn split_scope(name: &str) -> (&str, &str) {
+    match name.rfind("::") {
+        Some(end) => (&name[..end], &name[end + 2..]),
+        None => ("", name),
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+    /// Qualified name of the symbol `name` refers to from `scope`, looking in `scope` then in
+    /// each enclosing scope. A name starting with `::` is only looked up at the top level.
+    pub fn resolve_from(&self, scope: &str, name: &str) -> Option<String> {
+        if let Some(name) = name.strip_prefix("::") {
+            return Some(name.to_string()).filter(|name| self.symbols.contains_key(name));
+        }
+        let mut scope = scope;
+        loop {
+            let qualified = qualify(scope, name);
+            if self.symbols.contains_key(&qualified) {
+                return Some(qualified);
+            }
+            if scope.is_empty() {
+                return None;
+            }
+            scope = split_scope(scope).0;
+        }
+    }
+    /// Qualified name of the symbol `name` refers to from the current scope.
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        self.resolve_from(&self.scope, name)
+    }
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.resolve(name).and_then(|name| self.symbols.get(&name))
+    }
+    pub fn value(&self, name: &str) -> Option<i64> {
+        self.get(name).map(|symbol| symbol.value)
+    }
+    /// Check whether `name` should be encoded as an address, which is the case for labels and
+    /// for symbols that are not defined yet and could turn out to be labels.
+    pub fn is_address(&self, name: &str) -> bool {
+        self.get(name)
+            .is_none_or(|symbol| symbol.kind == SymbolKind::Label)
+    }
+    /// Symbol `name` defined in the current scope itself, as opposed to an enclosing one.
+    pub fn local(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(&qualify(&self.scope, name))
+    }
+    /// Define a symbol in the current scope. Only variables may be defined again, and only as
+    /// variables.
+    pub fn define(
+        &mut self,
+        name: &str,
+        value: i64,
+        kind: SymbolKind,
+        line: usize,
+    ) -> std::result::Result<(), &'static str> {
+        if let Some(existing) = self.local(name) {
+            if existing.kind != SymbolKind::Variable || kind != SymbolKind::Variable {
+                return Err("symbol already defined");
+            }
+        }
+        self.symbols
+            .insert(qualify(&self.scope, name), Symbol { value, kind, line });
+        Ok(())
+    }
+    /// Symbols by qualified name.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Symbol)> {
+        self.symbols.iter()
+    }
+    /// Every scope holding symbols, including the enclosing scopes of nested ones, sorted
+    /// so that each scope comes before the scopes inside it.
+    pub fn scopes(&self) -> Vec<&str> {
+        let mut scopes: Vec<&str> = vec![];
+        for name in self.symbols.keys() {
+            let mut scope = split_scope(name).0;
+            while !scope.is_empty() {
+                scopes.push(scope);
+                scope = split_scope(scope).0;
+            }
+        }
+        scopes.push("");
+        scopes.sort_unstable();
+        scopes.dedup();
+        scopes
+    }
+    /// Symbols defined directly in `scope`, by name within the scope, sorted by name.
+    pub fn members(&self, scope: &str) -> Vec<(&str, &Symbol)> {
+        let mut members: Vec<(&str, &Symbol)> = self
+            .symbols
+            .iter()
+            .filter_map(|(name, symbol)| match split_scope(name) {
+                (parent, name) if parent == scope => Some((name, symbol)),
+                _ => None,
+            })
+            .collect();
+        members.sort_unstable_by_key(|(name, _)| *name);
+        members
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn scoped_lookup() {
+        let mut symbols = SymbolTable::new();
+        symbols.define("x", 1, SymbolKind::Constant, 1).unwrap();
+        symbols.define("draw", 2, SymbolKind::Label, 2).unwrap();
+        symbols.scope = "draw".to_string();
+        symbols.define("x", 3, SymbolKind::Constant, 3).unwrap();
+        symbols.define("loop", 4, SymbolKind::Label, 4).unwrap();
+        symbols.scope = "draw::inner".to_string();
+        assert_eq!(symbols.value("x"), Some(3));
+        assert_eq!(symbols.value("::x"), Some(1));
+        assert_eq!(symbols.value("draw::loop"), Some(4));
+        assert_eq!(symbols.resolve("loop").as_deref(), Some("draw::loop"));
+        symbols.scope = String::new();
+        assert_eq!(symbols.value("loop"), None);
+        assert_eq!(symbols.scopes(), vec!["", "draw"]);
+        let members: Vec<&str> = symbols
+            .members("draw")
+            .iter()
+            .map(|(name, _)| *name)
+            .collect();
+        assert_eq!(members, vec!["loop", "x"]);
+    }
+}